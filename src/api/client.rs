@@ -1,9 +1,14 @@
-use reqwest::{Client, StatusCode};
-use std::time::Duration;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, instrument};
 
-use super::models::{AuthResponse, Metric, Stats};
-use crate::error::{AppError, Result};
+use super::models::{
+    AuthResponse, EventDataValue, Metric, MetricType, PageviewSeries, Stats, TimeUnit, Website,
+};
+use crate::{
+    error::{AppError, Result},
+    metrics,
+};
 
 const API_TIMEOUT: Duration = Duration::from_secs(30);
 
@@ -30,14 +35,15 @@ impl UmamiClient {
     pub async fn authenticate(&self, username: &str, password: &str) -> Result<String> {
         debug!("Authenticating with Umami API");
 
-        let response = self
+        let request = self
             .client
             .post(format!("{}/api/auth/login", self.base_url))
             .json(&serde_json::json!({
                 "username": username,
                 "password": password,
-            }))
-            .send()
+            }));
+        let response = self
+            .send("login", request)
             .await
             .map_err(|e| AppError::api(format!("Authentication request failed: {e}")))?;
 
@@ -61,6 +67,22 @@ impl UmamiClient {
         }
     }
 
+    #[instrument(skip(self, token))]
+    pub async fn get_website(&self, token: &str, website_id: &str) -> Result<Website> {
+        debug!("Fetching website {}", website_id);
+
+        let request = self
+            .client
+            .get(format!("{}/api/websites/{}", self.base_url, website_id))
+            .bearer_auth(token);
+        let response = self
+            .send("website", request)
+            .await
+            .map_err(|e| AppError::api(format!("Failed to fetch website: {e}")))?;
+
+        self.handle_response(response).await
+    }
+
     #[instrument(skip(self, token))]
     pub async fn get_stats(
         &self,
@@ -71,7 +93,7 @@ impl UmamiClient {
     ) -> Result<Stats> {
         debug!("Fetching stats for website {}", website_id);
 
-        let response = self
+        let request = self
             .client
             .get(format!(
                 "{}/api/websites/{}/stats",
@@ -81,20 +103,58 @@ impl UmamiClient {
                 ("startAt", start_at.to_string()),
                 ("endAt", end_at.to_string()),
             ])
-            .bearer_auth(token)
-            .send()
+            .bearer_auth(token);
+        let response = self
+            .send("stats", request)
             .await
             .map_err(|e| AppError::api(format!("Failed to fetch stats: {e}")))?;
 
         self.handle_response(response).await
     }
 
+    #[instrument(skip(self, token))]
+    pub async fn get_pageviews(
+        &self,
+        token: &str,
+        website_id: &str,
+        start_at: i64,
+        end_at: i64,
+        unit: TimeUnit,
+        timezone: &str,
+    ) -> Result<PageviewSeries> {
+        debug!(
+            "Fetching pageviews by {} for website {}",
+            unit.as_str(),
+            website_id
+        );
+
+        let request = self
+            .client
+            .get(format!(
+                "{}/api/websites/{}/pageviews",
+                self.base_url, website_id
+            ))
+            .query(&[
+                ("startAt", start_at.to_string()),
+                ("endAt", end_at.to_string()),
+                ("unit", unit.as_str().to_string()),
+                ("timezone", timezone.to_string()),
+            ])
+            .bearer_auth(token);
+        let response = self
+            .send("pageviews", request)
+            .await
+            .map_err(|e| AppError::api(format!("Failed to fetch pageviews: {e}")))?;
+
+        self.handle_response(response).await
+    }
+
     #[instrument(skip(self, token))]
     pub async fn get_metrics(
         &self,
         token: &str,
         website_id: &str,
-        metric_type: &str,
+        metric_type: MetricType,
         start_at: i64,
         end_at: i64,
         limit: u32,
@@ -104,26 +164,27 @@ impl UmamiClient {
             metric_type, website_id, limit
         );
 
-        let response = self
+        let request = self
             .client
             .get(format!(
                 "{}/api/websites/{}/metrics",
                 self.base_url, website_id
             ))
             .query(&[
-                ("type", metric_type.to_string()),
+                ("type", metric_type.as_str().to_string()),
                 ("startAt", start_at.to_string()),
                 ("endAt", end_at.to_string()),
                 ("limit", limit.to_string()),
             ])
-            .bearer_auth(token)
-            .send()
+            .bearer_auth(token);
+        let response = self
+            .send("metrics", request)
             .await
             .map_err(|e| AppError::api(format!("Failed to fetch metrics: {e}")))?;
 
         let mut metrics: Vec<Metric> = self.handle_response(response).await?;
 
-        if metric_type == "country" {
+        if metric_type == MetricType::Country {
             for metric in &mut metrics {
                 debug!("Processing country code: '{}'", metric.x);
                 metric.x = crate::config::get_country_name(&metric.x);
@@ -133,6 +194,56 @@ impl UmamiClient {
         Ok(metrics)
     }
 
+    #[instrument(skip(self, token))]
+    pub async fn get_event_data_values(
+        &self,
+        token: &str,
+        website_id: &str,
+        event: &str,
+        property: &str,
+        start_at: i64,
+        end_at: i64,
+    ) -> Result<Vec<Metric>> {
+        debug!(
+            "Fetching {} values of event {} for website {}",
+            property, event, website_id
+        );
+
+        let request = self
+            .client
+            .get(format!(
+                "{}/api/websites/{}/event-data/values",
+                self.base_url, website_id
+            ))
+            .query(&[
+                ("event", event.to_string()),
+                ("propertyName", property.to_string()),
+                ("startAt", start_at.to_string()),
+                ("endAt", end_at.to_string()),
+            ])
+            .bearer_auth(token);
+        let response = self
+            .send("event_data_values", request)
+            .await
+            .map_err(|e| AppError::api(format!("Failed to fetch event data: {e}")))?;
+
+        let values: Vec<EventDataValue> = self.handle_response(response).await?;
+
+        Ok(values.into_iter().map(Metric::from).collect())
+    }
+
+    /// Send a request, recording its latency under the endpoint name
+    async fn send(
+        &self,
+        endpoint: &'static str,
+        request: RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        let started = Instant::now();
+        let response = request.send().await;
+        metrics::observe_api_request(endpoint, started.elapsed());
+        response
+    }
+
     async fn handle_response<T>(&self, response: reqwest::Response) -> Result<T>
     where
         T: for<'de> serde::Deserialize<'de>,
@@ -144,7 +255,7 @@ impl UmamiClient {
                 .map_err(|e| AppError::api(format!("Failed to parse API response: {e}"))),
             StatusCode::UNAUTHORIZED => {
                 error!("API authentication failed");
-                Err(AppError::api("Authentication token expired or invalid"))
+                Err(AppError::Unauthorized)
             }
             StatusCode::NOT_FOUND => {
                 error!("API endpoint or resource not found");
@@ -242,4 +353,117 @@ mod tests {
         let result = client.get_stats("token", "test-id", 0, 1000).await;
         assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_get_pageviews() {
+        let mut server = Server::new_async().await;
+        let client = UmamiClient::new(server.url()).unwrap();
+
+        let _mock = server
+            .mock("GET", "/api/websites/test-id/pageviews")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("unit".into(), "hour".into()),
+                mockito::Matcher::UrlEncoded("timezone".into(), "Asia/Kolkata".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"pageviews": [{"x": "2025-01-01 10:00:00", "y": 8}],
+                    "sessions": [{"x": "2025-01-01 10:00:00", "y": 3}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let series = client
+            .get_pageviews("token", "test-id", 0, 1000, TimeUnit::Hour, "Asia/Kolkata")
+            .await
+            .unwrap();
+        assert_eq!(series.pageviews[0].x, "2025-01-01 10:00:00");
+        assert_eq!(series.pageviews[0].y, 8.0);
+        assert_eq!(series.sessions[0].y, 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_get_stats_unauthorized() {
+        let mut server = Server::new_async().await;
+        let client = UmamiClient::new(server.url()).unwrap();
+
+        let _mock = server
+            .mock("GET", "/api/websites/test-id/stats")
+            .match_query(mockito::Matcher::Any)
+            .with_status(401)
+            .create_async()
+            .await;
+
+        let result = client.get_stats("expired", "test-id", 0, 1000).await;
+        assert!(matches!(result.unwrap_err(), AppError::Unauthorized));
+    }
+
+    #[tokio::test]
+    async fn test_get_website_not_found() {
+        let mut server = Server::new_async().await;
+        let client = UmamiClient::new(server.url()).unwrap();
+
+        let _mock = server
+            .mock("GET", "/api/websites/missing-id")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let result = client.get_website("token", "missing-id").await;
+        assert!(matches!(result.unwrap_err(), AppError::Api(_)));
+    }
+
+    #[tokio::test]
+    async fn test_get_metrics_country_names() {
+        crate::config::load_country_map().await.ok();
+
+        let mut server = Server::new_async().await;
+        let client = UmamiClient::new(server.url()).unwrap();
+
+        let _mock = server
+            .mock("GET", "/api/websites/test-id/metrics")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "type".into(),
+                "country".into(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"[{"x": "DE", "y": 10}, {"x": "", "y": 2}]"#)
+            .create_async()
+            .await;
+
+        let metrics = client
+            .get_metrics("token", "test-id", MetricType::Country, 0, 1000, 10)
+            .await
+            .unwrap();
+        assert_eq!(metrics[0].x, "Germany");
+        assert_eq!(metrics[1].x, "Unknown");
+    }
+
+    #[tokio::test]
+    async fn test_get_event_data_values() {
+        let mut server = Server::new_async().await;
+        let client = UmamiClient::new(server.url()).unwrap();
+
+        let _mock = server
+            .mock("GET", "/api/websites/test-id/event-data/values")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("event".into(), "signup".into()),
+                mockito::Matcher::UrlEncoded("propertyName".into(), "plan".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"[{"value": "pro", "total": 12}, {"value": 3, "total": 4}]"#)
+            .create_async()
+            .await;
+
+        let values = client
+            .get_event_data_values("token", "test-id", "signup", "plan", 0, 1000)
+            .await
+            .unwrap();
+        assert_eq!(values[0].x, "pro");
+        assert_eq!(values[0].y, 12.0);
+        assert_eq!(values[1].x, "3");
+    }
 }
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::Add;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Stats {
     pub pageviews: f64,
     pub visitors: f64,
@@ -10,7 +13,7 @@ pub struct Stats {
     pub comparison: StatsComparison,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct StatsComparison {
     pub pageviews: f64,
     pub visitors: f64,
@@ -19,18 +22,193 @@ pub struct StatsComparison {
     pub totaltime: f64,
 }
 
+impl Add for StatsComparison {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            pageviews: self.pageviews + other.pageviews,
+            visitors: self.visitors + other.visitors,
+            visits: self.visits + other.visits,
+            bounces: self.bounces + other.bounces,
+            totaltime: self.totaltime + other.totaltime,
+        }
+    }
+}
+
+/// Stats of several websites add up, although visitors who visited more
+/// than one of them are counted once for each
+impl Add for Stats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            pageviews: self.pageviews + other.pageviews,
+            visitors: self.visitors + other.visitors,
+            visits: self.visits + other.visits,
+            bounces: self.bounces + other.bounces,
+            totaltime: self.totaltime + other.totaltime,
+            comparison: self.comparison + other.comparison,
+        }
+    }
+}
+
+impl Sum for Stats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+impl From<&Stats> for StatsComparison {
+    fn from(stats: &Stats) -> Self {
+        Self {
+            pageviews: stats.pageviews,
+            visitors: stats.visitors,
+            visits: stats.visits,
+            bounces: stats.bounces,
+            totaltime: stats.totaltime,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MetricValue {
     pub value: f64,
     pub prev: f64,
 }
 
+/// A dimension that Umami's metrics endpoint can rank values by
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricType {
+    Path,
+    Entry,
+    Exit,
+    Title,
+    Query,
+    Referrer,
+    Channel,
+    Hostname,
+    Browser,
+    Os,
+    Device,
+    Screen,
+    Language,
+    Country,
+    Region,
+    City,
+    Event,
+    Tag,
+    UtmSource,
+    UtmMedium,
+    UtmCampaign,
+    UtmContent,
+    UtmTerm,
+}
+
+impl MetricType {
+    /// The value of the `type` query parameter
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricType::Path => "path",
+            MetricType::Entry => "entry",
+            MetricType::Exit => "exit",
+            MetricType::Title => "title",
+            MetricType::Query => "query",
+            MetricType::Referrer => "referrer",
+            MetricType::Channel => "channel",
+            MetricType::Hostname => "hostname",
+            MetricType::Browser => "browser",
+            MetricType::Os => "os",
+            MetricType::Device => "device",
+            MetricType::Screen => "screen",
+            MetricType::Language => "language",
+            MetricType::Country => "country",
+            MetricType::Region => "region",
+            MetricType::City => "city",
+            MetricType::Event => "event",
+            MetricType::Tag => "tag",
+            MetricType::UtmSource => "utm_source",
+            MetricType::UtmMedium => "utm_medium",
+            MetricType::UtmCampaign => "utm_campaign",
+            MetricType::UtmContent => "utm_content",
+            MetricType::UtmTerm => "utm_term",
+        }
+    }
+}
+
+impl fmt::Display for MetricType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Metric {
     pub x: String,
     pub y: f64,
 }
 
+/// The granularity of a time series
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnit {
+    Hour,
+    Day,
+    Month,
+}
+
+impl TimeUnit {
+    /// The unit as used in Umami API queries
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeUnit::Hour => "hour",
+            TimeUnit::Day => "day",
+            TimeUnit::Month => "month",
+        }
+    }
+}
+
+/// A point of a time series, where `x` is the start of its bucket in the
+/// timezone of the query
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SeriesPoint {
+    pub x: String,
+    pub y: f64,
+}
+
+/// Pageviews and sessions over time. Buckets without traffic are left out.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PageviewSeries {
+    pub pageviews: Vec<SeriesPoint>,
+    pub sessions: Vec<SeriesPoint>,
+}
+
+/// A value of an event data property and the number of events that had it
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct EventDataValue {
+    pub value: serde_json::Value,
+    pub total: f64,
+}
+
+impl From<EventDataValue> for Metric {
+    fn from(value: EventDataValue) -> Self {
+        let x = match value.value {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        };
+        Self { x, y: value.total }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Website {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct AuthResponse {
     pub token: String,
@@ -0,0 +1,191 @@
+use futures::stream::{self, StreamExt};
+use std::fmt;
+use tracing::{debug, info};
+
+use crate::{
+    api::UmamiClient,
+    config::{validation::validate_config, Config, WebsiteConfig},
+    email::Mailer,
+    error::{AppError, Result},
+};
+
+const COLUMNS: [&str; 3] = ["config", "auth", "access"];
+
+enum Outcome {
+    Pass,
+    Fail(String),
+    Skipped,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "ok"),
+            Outcome::Fail(_) => write!(f, "FAIL"),
+            Outcome::Skipped => write!(f, "-"),
+        }
+    }
+}
+
+impl<T> From<Result<T>> for Outcome {
+    fn from(result: Result<T>) -> Self {
+        match result {
+            Ok(_) => Outcome::Pass,
+            Err(e) => Outcome::Fail(e.to_string()),
+        }
+    }
+}
+
+/// The result of every check step for a single target, in `COLUMNS` order
+struct CheckRow {
+    target: String,
+    outcomes: [Outcome; 3],
+}
+
+impl CheckRow {
+    fn failures(&self) -> impl Iterator<Item = (&str, &str)> {
+        COLUMNS
+            .iter()
+            .zip(&self.outcomes)
+            .filter_map(|(column, outcome)| match outcome {
+                Outcome::Fail(msg) => Some((*column, msg.as_str())),
+                _ => None,
+            })
+    }
+}
+
+/// Verify the configuration, Umami access for every website reported on,
+/// including those only used by groups, and the SMTP login, printing a
+/// pass/fail matrix. Returns an error if any check failed.
+pub async fn run(config: &Config) -> Result<()> {
+    info!("Checking configuration and connectivity");
+
+    let mut rows = vec![
+        CheckRow {
+            target: "config".to_string(),
+            outcomes: [
+                validate_config(config).into(),
+                Outcome::Skipped,
+                Outcome::Skipped,
+            ],
+        },
+        check_smtp(config).await,
+    ];
+
+    let mut websites: Vec<_> = config.report_websites().collect();
+    websites.sort_by_key(|(name, _)| *name);
+
+    let website_rows = stream::iter(websites)
+        .map(|(name, website)| check_website(name, website))
+        .buffered(config.app.max_concurrent_jobs.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    rows.extend(website_rows);
+
+    print_matrix(&rows);
+
+    let failures = rows.iter().flat_map(CheckRow::failures).count();
+    if failures > 0 {
+        return Err(AppError::task(format!("{failures} checks failed")));
+    }
+
+    Ok(())
+}
+
+async fn check_smtp(config: &Config) -> CheckRow {
+    debug!(
+        "Checking SMTP server {}:{}",
+        config.smtp.host, config.smtp.port
+    );
+
+    let validation = config.smtp.validate();
+    let auth = if validation.is_ok() {
+        match Mailer::new(&config.smtp) {
+            Ok(mailer) => mailer.test_connection().await.into(),
+            Err(e) => Outcome::Fail(e.to_string()),
+        }
+    } else {
+        Outcome::Skipped
+    };
+
+    CheckRow {
+        target: "smtp".to_string(),
+        outcomes: [validation.into(), auth, Outcome::Skipped],
+    }
+}
+
+async fn check_website(name: &str, website: &WebsiteConfig) -> CheckRow {
+    debug!("Checking website {}", name);
+
+    let row = |outcomes| CheckRow {
+        target: format!("websites.{name}"),
+        outcomes,
+    };
+
+    if let Err(e) = website.validate() {
+        return row([
+            Outcome::Fail(e.to_string()),
+            Outcome::Skipped,
+            Outcome::Skipped,
+        ]);
+    }
+
+    let client = match UmamiClient::new(website.base_url.clone()) {
+        Ok(client) => client,
+        Err(e) => {
+            return row([
+                Outcome::Pass,
+                Outcome::Fail(e.to_string()),
+                Outcome::Skipped,
+            ]);
+        }
+    };
+
+    let token = match client
+        .authenticate(&website.username, &website.password)
+        .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            return row([
+                Outcome::Pass,
+                Outcome::Fail(e.to_string()),
+                Outcome::Skipped,
+            ]);
+        }
+    };
+
+    let access = client
+        .get_website(&token, &website.id)
+        .await
+        .map_err(|e| AppError::api(format!("Website {} is not readable: {e}", website.id)));
+
+    row([Outcome::Pass, Outcome::Pass, access.into()])
+}
+
+fn print_matrix(rows: &[CheckRow]) {
+    let width = rows
+        .iter()
+        .map(|row| row.target.len())
+        .chain(std::iter::once("target".len()))
+        .max()
+        .unwrap_or_default();
+
+    let header: String = COLUMNS.iter().map(|c| format!("  {c:<8}")).collect();
+    println!("{:<width$}{}", "target", header.trim_end());
+
+    for row in rows {
+        let cells: String = row
+            .outcomes
+            .iter()
+            .map(|o| format!("  {:<8}", o.to_string()))
+            .collect();
+        println!("{:<width$}{}", row.target, cells.trim_end());
+    }
+
+    for row in rows {
+        for (column, msg) in row.failures() {
+            println!("{} ({}): {}", row.target, column, msg);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+    history::ledger::{Delivery, DeliveryStatus, Ledger},
+    report::period,
+};
+
+/// Print every recorded delivery attempt, grouped by website and period, for
+/// the configured websites and groups whose periods overlap `from`..=`to`.
+/// Dry runs and reports that failed to generate are listed as attempts too.
+pub async fn run(config: &Config, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<()> {
+    if let (Some(from), Some(to)) = (from, to) {
+        period::check_date_span(from, to)?;
+    }
+    let dir = config.app.data_dir.as_ref().ok_or_else(|| {
+        AppError::config("No data_dir is configured, so no history has been kept")
+    })?;
+    let deliveries = Ledger::open(dir).await?.deliveries().await;
+    let timezones = timezones(config)?;
+
+    let selected = select(&deliveries, &timezones, from, to);
+    print_deliveries(&selected);
+    Ok(())
+}
+
+/// The timezone of every configured website and group, by website id or group
+/// name
+fn timezones(config: &Config) -> Result<HashMap<&str, Tz>> {
+    config
+        .websites
+        .values()
+        .map(|website| (website.id.as_str(), website.timezone.as_str()))
+        .chain(
+            config
+                .groups
+                .iter()
+                .map(|(name, group)| (name.as_str(), group.timezone.as_str())),
+        )
+        .map(|(id, timezone)| Ok((id, period::parse_timezone(timezone)?)))
+        .collect()
+}
+
+/// The deliveries of the given websites whose period, in the website's
+/// timezone, overlaps the dates
+fn select<'a>(
+    deliveries: &'a [Delivery],
+    timezones: &HashMap<&str, Tz>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Vec<&'a Delivery> {
+    deliveries
+        .iter()
+        .filter(|delivery| {
+            let Some(tz) = timezones.get(delivery.key.website_id.as_str()) else {
+                return false;
+            };
+            let first = delivery.key.start.with_timezone(tz).date_naive();
+            let last = delivery.key.end.with_timezone(tz).date_naive();
+            from.is_none_or(|from| last >= from) && to.is_none_or(|to| first <= to)
+        })
+        .collect()
+}
+
+fn print_deliveries(deliveries: &[&Delivery]) {
+    if deliveries.is_empty() {
+        println!("No deliveries recorded");
+        return;
+    }
+
+    let mut periods: BTreeMap<_, Vec<&Delivery>> = BTreeMap::new();
+    for delivery in deliveries {
+        let key = &delivery.key;
+        periods
+            .entry((key.start, &delivery.website_name, &key.report_type, key.end))
+            .or_default()
+            .push(delivery);
+    }
+
+    let width = deliveries
+        .iter()
+        .map(|delivery| delivery.key.recipient.len())
+        .max()
+        .unwrap_or_default();
+
+    for ((_, website_name, report_type, _), attempts) in &periods {
+        println!(
+            "{} - {} - {}",
+            website_name, report_type, attempts[0].period
+        );
+        for delivery in attempts {
+            println!(
+                "  {}  {:<13}  {:<6}  {:<width$}  {:>6} ms",
+                delivery.attempted_at.format("%Y-%m-%d %H:%M:%S"),
+                delivery.status.as_str(),
+                delivery.key.channel.as_str(),
+                delivery.key.recipient,
+                delivery.duration_ms,
+            );
+            if let Some(error) = &delivery.error {
+                println!("    {error}");
+            }
+        }
+    }
+
+    let count = |matches: fn(&DeliveryStatus) -> bool| {
+        deliveries
+            .iter()
+            .filter(|delivery| matches(&delivery.status))
+            .count()
+    };
+    println!(
+        "{} deliveries over {} periods, {} failed, {} dry runs",
+        deliveries.len(),
+        periods.len(),
+        count(DeliveryStatus::is_failure),
+        count(|status| *status == DeliveryStatus::DryRun)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::ledger::{Channel, DeliveryKey};
+    use crate::report::models::ReportData;
+    use chrono::{Datelike, Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_select() {
+        let delivery = |website_id: &str, day: u32| {
+            let mut report = ReportData::sample();
+            report.website_id = website_id.to_string();
+            // Midnight in Berlin
+            report.start = Utc.with_ymd_and_hms(2025, 1, day - 1, 23, 0, 0).unwrap();
+            report.end = report.start + Duration::days(1) - Duration::seconds(1);
+            let key = DeliveryKey::new(&report, "a@example.com", Channel::Email);
+            Delivery::new(key, &report, &Ok(()), std::time::Duration::ZERO)
+        };
+        let deliveries = [
+            delivery("site", 9),
+            delivery("site", 10),
+            delivery("site", 11),
+            delivery("removed", 10),
+        ];
+        let timezones = HashMap::from([("site", "Europe/Berlin".parse().unwrap())]);
+        let date = |day| NaiveDate::from_ymd_opt(2025, 1, day);
+
+        assert_eq!(select(&deliveries, &timezones, None, None).len(), 3);
+
+        let selected = select(&deliveries, &timezones, date(10), date(10));
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].key.start.day(), 9);
+
+        assert_eq!(select(&deliveries, &timezones, date(10), None).len(), 2);
+        assert_eq!(select(&deliveries, &timezones, None, date(9)).len(), 1);
+    }
+}
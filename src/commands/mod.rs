@@ -0,0 +1,3 @@
+pub mod check;
+pub mod history;
+pub mod send_test;
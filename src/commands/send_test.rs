@@ -0,0 +1,37 @@
+use std::sync::Arc;
+use tracing::info;
+
+use crate::{
+    config::Config,
+    email::Mailer,
+    error::Result,
+    report::{self, generator::ReportGenerator, models::ReportData},
+};
+
+/// Render the report template from sample data and send it to `recipient`
+/// through the configured SMTP server, printing the server's response. A
+/// rejection by the server is returned as an error by the mailer.
+pub async fn run(config: &Config, recipient: &str) -> Result<()> {
+    config.smtp.validate()?;
+
+    let generator = ReportGenerator::new(Arc::new(report::template_registry()?), None, None);
+    let data = ReportData::sample();
+    let html = generator.render_report(&data)?;
+    let images = data.sparklines.images(&data.website_id)?;
+
+    info!("Sending test report to {}", recipient);
+    let mailer = Mailer::new(&config.smtp)?;
+    let response = mailer
+        .send_html(
+            recipient,
+            "Test Analytics Report - umami-alerts",
+            &html,
+            &images,
+        )
+        .await?;
+
+    let message: Vec<_> = response.message().collect();
+    println!("{} {}", response.code(), message.join(" "));
+
+    Ok(())
+}
@@ -1,18 +1,114 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 use url::Url;
 
+use crate::api::models::{MetricType, TimeUnit};
 use crate::error::{AppError, Result};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub smtp: SmtpConfig,
     pub websites: HashMap<String, WebsiteConfig>,
+    /// Groups of websites reported on together, by name
+    #[serde(default)]
+    pub groups: HashMap<String, GroupConfig>,
     #[serde(default)]
     pub app: AppConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub exporter: ExporterConfig,
+}
+
+/// Settings of the long running `daemon` mode
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DaemonConfig {
+    /// Address serving the Prometheus metrics on `/metrics`
+    #[serde(default = "default_listen")]
+    pub listen: SocketAddr,
+    /// Minutes between runs; reports already sent are skipped, so runs can be
+    /// more frequent than reports
+    #[serde(default = "default_interval_minutes")]
+    pub interval_minutes: u64,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            listen: default_listen(),
+            interval_minutes: default_interval_minutes(),
+        }
+    }
+}
+
+/// Settings of the `exporter` mode, which serves Umami stats as Prometheus
+/// gauges
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExporterConfig {
+    /// Address serving the gauges on `/metrics`
+    #[serde(default = "default_exporter_listen")]
+    pub listen: SocketAddr,
+    /// Seconds between refreshes of the stats
+    #[serde(default = "default_exporter_interval_seconds")]
+    pub interval_seconds: u64,
+    /// Length of the rolling window the stats cover, ending at each refresh
+    #[serde(default = "default_exporter_window_minutes")]
+    pub window_minutes: u64,
+    /// Metrics whose top values are exported
+    #[serde(default = "default_exporter_metrics")]
+    pub metrics: Vec<MetricType>,
+    /// Number of top values exported for each metric
+    #[serde(default = "default_exporter_limit")]
+    pub limit: u32,
+}
+
+impl Default for ExporterConfig {
+    fn default() -> Self {
+        Self {
+            listen: default_exporter_listen(),
+            interval_seconds: default_exporter_interval_seconds(),
+            window_minutes: default_exporter_window_minutes(),
+            metrics: default_exporter_metrics(),
+            limit: default_exporter_limit(),
+        }
+    }
+}
+
+fn default_exporter_listen() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 9899))
+}
+
+fn default_exporter_interval_seconds() -> u64 {
+    60
+}
+
+fn default_exporter_window_minutes() -> u64 {
+    24 * 60
+}
+
+fn default_exporter_metrics() -> Vec<MetricType> {
+    vec![
+        MetricType::Path,
+        MetricType::Referrer,
+        MetricType::Browser,
+        MetricType::Country,
+    ]
+}
+
+fn default_exporter_limit() -> u32 {
+    10
+}
+
+fn default_listen() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 9898))
+}
+
+fn default_interval_minutes() -> u64 {
+    60
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -21,26 +117,86 @@ pub struct AppConfig {
     pub debug: bool,
     #[serde(default)]
     pub dry_run: bool,
+    /// Send each recipient a single email covering all of their websites
+    #[serde(default)]
+    pub digest: bool,
     #[serde(default = "default_max_concurrent_jobs")]
     pub max_concurrent_jobs: usize,
     #[serde(default = "default_report_type")]
     pub report_type: ReportType,
+    /// Directory keeping the history of generated reports; nothing is kept
+    /// when unset
+    #[serde(default)]
+    pub data_dir: Option<PathBuf>,
+    /// The most periods missed since the last sent report that are sent
+    /// before the latest one, when a data directory is kept; 0 disables
+    /// catching up
+    #[serde(default = "default_max_catch_up_periods")]
+    pub max_catch_up_periods: usize,
+    /// Send reports again even if the ledger shows they were already sent;
+    /// only set from the command line
+    #[serde(skip)]
+    pub force: bool,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
 pub enum ReportType {
     Daily,
+    /// The last seven days
     Weekly,
+    /// The last calendar week, Monday through Sunday
+    IsoWeekly,
     Monthly,
+    Quarterly,
+    Yearly,
+    /// The current calendar week up to yesterday
+    WeekToDate,
+    /// The current calendar month up to yesterday
+    MonthToDate,
+}
+
+impl ReportType {
+    /// The bucket size of the traffic chart for reports of this type
+    pub fn series_unit(&self) -> TimeUnit {
+        match self {
+            ReportType::Daily => TimeUnit::Hour,
+            ReportType::Yearly => TimeUnit::Month,
+            _ => TimeUnit::Day,
+        }
+    }
+
+    /// Whether consecutive reports of this type cover adjacent periods, rather
+    /// than overlapping ones that make up for a missed report
+    pub fn has_adjacent_periods(&self) -> bool {
+        !matches!(
+            self,
+            ReportType::Weekly | ReportType::WeekToDate | ReportType::MonthToDate
+        )
+    }
+
+    /// Whether reports of this type show traffic by weekday and hour
+    pub fn has_heatmap(&self) -> bool {
+        matches!(
+            self,
+            ReportType::Daily | ReportType::Weekly | ReportType::IsoWeekly | ReportType::WeekToDate
+        )
+    }
 }
 
 impl fmt::Display for ReportType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The titles also key the history and ledger, so each needs its own
         match self {
             ReportType::Daily => write!(f, "Daily"),
             ReportType::Weekly => write!(f, "Weekly"),
+            ReportType::IsoWeekly => write!(f, "ISO Weekly"),
             ReportType::Monthly => write!(f, "Monthly"),
+            ReportType::Quarterly => write!(f, "Quarterly"),
+            ReportType::Yearly => write!(f, "Yearly"),
+            ReportType::WeekToDate => write!(f, "Week-to-Date"),
+            ReportType::MonthToDate => write!(f, "Month-to-Date"),
         }
     }
 }
@@ -49,6 +205,10 @@ fn default_max_concurrent_jobs() -> usize {
     4
 }
 
+fn default_max_catch_up_periods() -> usize {
+    7
+}
+
 fn default_report_type() -> ReportType {
     ReportType::Daily
 }
@@ -58,8 +218,12 @@ impl Default for AppConfig {
         Self {
             debug: false,
             dry_run: false,
+            digest: false,
             max_concurrent_jobs: default_max_concurrent_jobs(),
             report_type: default_report_type(),
+            data_dir: None,
+            max_catch_up_periods: default_max_catch_up_periods(),
+            force: false,
         }
     }
 }
@@ -99,6 +263,207 @@ pub struct WebsiteConfig {
     pub timezone: String,
     #[serde(default)]
     pub disabled: bool,
+    #[serde(default)]
+    pub comparison: Comparison,
+    #[serde(default = "default_sections")]
+    pub sections: Vec<SectionConfig>,
+    /// Event data properties to break down in the events section, by event name
+    #[serde(default)]
+    pub event_properties: BTreeMap<String, Vec<String>>,
+}
+
+/// Several websites whose stats and top lists are combined into one report
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GroupConfig {
+    pub name: String,
+    /// Names of the member websites, as in their `[websites.<name>]` sections
+    pub websites: Vec<String>,
+    pub recipients: Vec<String>,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub comparison: Comparison,
+    #[serde(default = "default_sections")]
+    pub sections: Vec<SectionConfig>,
+    #[serde(default)]
+    pub event_properties: BTreeMap<String, Vec<String>>,
+}
+
+/// A ranked list shown in the report, in the order they are configured
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SectionConfig {
+    #[serde(rename = "type")]
+    pub kind: SectionKind,
+    /// Number of items to show, defaults to a per-section limit
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+impl SectionConfig {
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or_else(|| self.kind.default_limit())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionKind {
+    Pages,
+    EntryPages,
+    ExitPages,
+    Titles,
+    QueryParams,
+    Referrers,
+    Channels,
+    Hostnames,
+    Browsers,
+    Os,
+    Devices,
+    Screens,
+    Languages,
+    Countries,
+    Regions,
+    Cities,
+    Events,
+    Tags,
+    UtmSource,
+    UtmMedium,
+    UtmCampaign,
+    UtmContent,
+    UtmTerm,
+    /// Channels and UTM sources, mediums and campaigns side by side
+    Campaigns,
+}
+
+impl SectionKind {
+    /// The Umami metric type the section is built from, if it is a single ranking
+    pub fn metric_type(&self) -> Option<MetricType> {
+        let metric_type = match self {
+            SectionKind::Pages => MetricType::Path,
+            SectionKind::EntryPages => MetricType::Entry,
+            SectionKind::ExitPages => MetricType::Exit,
+            SectionKind::Titles => MetricType::Title,
+            SectionKind::QueryParams => MetricType::Query,
+            SectionKind::Referrers => MetricType::Referrer,
+            SectionKind::Channels => MetricType::Channel,
+            SectionKind::Hostnames => MetricType::Hostname,
+            SectionKind::Browsers => MetricType::Browser,
+            SectionKind::Os => MetricType::Os,
+            SectionKind::Devices => MetricType::Device,
+            SectionKind::Screens => MetricType::Screen,
+            SectionKind::Languages => MetricType::Language,
+            SectionKind::Countries => MetricType::Country,
+            SectionKind::Regions => MetricType::Region,
+            SectionKind::Cities => MetricType::City,
+            SectionKind::Events => MetricType::Event,
+            SectionKind::Tags => MetricType::Tag,
+            SectionKind::UtmSource => MetricType::UtmSource,
+            SectionKind::UtmMedium => MetricType::UtmMedium,
+            SectionKind::UtmCampaign => MetricType::UtmCampaign,
+            SectionKind::UtmContent => MetricType::UtmContent,
+            SectionKind::UtmTerm => MetricType::UtmTerm,
+            SectionKind::Campaigns => return None,
+        };
+        Some(metric_type)
+    }
+
+    /// Whether items are shown as a share of all visits, as every visit has
+    /// exactly one entry and one exit page
+    pub fn shows_visit_share(&self) -> bool {
+        matches!(self, SectionKind::EntryPages | SectionKind::ExitPages)
+    }
+
+    /// The metric types shown side by side in a combined section
+    pub fn columns(&self) -> &'static [(MetricType, &'static str)] {
+        match self {
+            SectionKind::Campaigns => &[
+                (MetricType::Channel, "Channel"),
+                (MetricType::UtmSource, "Source"),
+                (MetricType::UtmMedium, "Medium"),
+                (MetricType::UtmCampaign, "Campaign"),
+            ],
+            _ => &[],
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            SectionKind::Pages => "Top Pages",
+            SectionKind::EntryPages => "Entry Pages",
+            SectionKind::ExitPages => "Exit Pages",
+            SectionKind::Titles => "Page Titles",
+            SectionKind::QueryParams => "Query Parameters",
+            SectionKind::Referrers => "Referrers",
+            SectionKind::Channels => "Channels",
+            SectionKind::Hostnames => "Hostnames",
+            SectionKind::Browsers => "Browsers",
+            SectionKind::Os => "Operating Systems",
+            SectionKind::Devices => "Devices",
+            SectionKind::Screens => "Screen Sizes",
+            SectionKind::Languages => "Languages",
+            SectionKind::Countries => "Countries",
+            SectionKind::Regions => "Regions",
+            SectionKind::Cities => "Cities",
+            SectionKind::Events => "Events",
+            SectionKind::Tags => "Tags",
+            SectionKind::UtmSource => "UTM Sources",
+            SectionKind::UtmMedium => "UTM Mediums",
+            SectionKind::UtmCampaign => "UTM Campaigns",
+            SectionKind::UtmContent => "UTM Content",
+            SectionKind::UtmTerm => "UTM Terms",
+            SectionKind::Campaigns => "Campaigns",
+        }
+    }
+
+    pub fn default_limit(&self) -> u32 {
+        match self {
+            SectionKind::Pages
+            | SectionKind::EntryPages
+            | SectionKind::ExitPages
+            | SectionKind::Titles
+            | SectionKind::Countries
+            | SectionKind::Regions
+            | SectionKind::Cities
+            | SectionKind::Events => 10,
+            _ => 5,
+        }
+    }
+}
+
+fn default_sections() -> Vec<SectionConfig> {
+    [
+        SectionKind::Pages,
+        SectionKind::Referrers,
+        SectionKind::Browsers,
+        SectionKind::Devices,
+        SectionKind::Countries,
+    ]
+    .into_iter()
+    .map(|kind| SectionConfig { kind, limit: None })
+    .collect()
+}
+
+/// The baseline that report numbers are compared against
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    /// The period of the same length immediately before the report period
+    #[default]
+    PreviousPeriod,
+    /// The same period one year earlier
+    LastYear,
+    /// The same period shifted back one week, e.g. the same weekday for daily reports
+    LastWeek,
+}
+
+impl Comparison {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Comparison::PreviousPeriod => "vs previous period",
+            Comparison::LastYear => "vs last year",
+            Comparison::LastWeek => "vs last week",
+        }
+    }
 }
 
 fn default_timezone() -> String {
@@ -118,6 +483,90 @@ impl Config {
     pub fn enabled_websites(&self) -> impl Iterator<Item = (&String, &WebsiteConfig)> {
         self.websites.iter().filter(|(_, config)| !config.disabled)
     }
+
+    /// The websites reports are fetched from: the enabled ones, and those of
+    /// every group even when disabled
+    pub fn report_websites(&self) -> impl Iterator<Item = (&String, &WebsiteConfig)> {
+        self.websites
+            .iter()
+            .filter(|(name, config)| !config.disabled || self.is_grouped(name))
+    }
+
+    /// Whether a website is in any group
+    pub fn is_grouped(&self, name: &str) -> bool {
+        self.groups
+            .values()
+            .any(|group| group.websites.iter().any(|website| website == name))
+    }
+
+    /// Restrict the configured websites and groups to those named in `include`
+    /// (all, if empty) minus those named in `exclude`. Groups that are not
+    /// named are kept when all of their websites are selected. The websites of
+    /// a kept group stay available for its report, but are disabled unless
+    /// they were selected themselves.
+    pub fn select_websites(&mut self, include: &[String], exclude: &[String]) -> Result<()> {
+        if let Some(name) = include
+            .iter()
+            .chain(exclude)
+            .find(|name| !self.websites.contains_key(*name) && !self.groups.contains_key(*name))
+        {
+            return Err(AppError::Config(format!(
+                "Unknown website or group: {name}"
+            )));
+        }
+        if let Some(name) = include.iter().find(|name| {
+            self.websites
+                .get(*name)
+                .is_some_and(|website| website.disabled)
+        }) {
+            return Err(AppError::Config(format!(
+                "Website {name} is disabled in the configuration"
+            )));
+        }
+
+        let selected = |name: &String| {
+            (include.is_empty() || include.contains(name)) && !exclude.contains(name)
+        };
+
+        self.groups.retain(|name, group| {
+            if exclude.contains(name) {
+                tracing::info!("Skipping group {}, as it is excluded", name);
+                return false;
+            }
+            if include.contains(name) {
+                return true;
+            }
+            match group.websites.iter().find(|website| !selected(website)) {
+                Some(website) => {
+                    tracing::info!(
+                        "Skipping group {}, as its website {} is not selected",
+                        name,
+                        website
+                    );
+                    false
+                }
+                None => true,
+            }
+        });
+
+        let members: HashSet<&String> = self
+            .groups
+            .values()
+            .flat_map(|group| &group.websites)
+            .collect();
+        self.websites.retain(|name, website| {
+            if selected(name) {
+                true
+            } else if members.contains(name) {
+                website.disabled = true;
+                true
+            } else {
+                false
+            }
+        });
+
+        Ok(())
+    }
 }
 
 impl SmtpConfig {
@@ -174,22 +623,46 @@ impl WebsiteConfig {
             return Err(AppError::Config("Password cannot be empty".to_string()));
         }
 
-        if self.recipients.is_empty() {
-            return Err(AppError::Config(
-                "At least one recipient is required".to_string(),
-            ));
+        validate_recipients(&self.recipients)?;
+        validate_sections(&self.sections, &self.event_properties)?;
+
+        // Validate timezone
+        self.timezone
+            .parse::<chrono_tz::Tz>()
+            .map_err(|e| AppError::Config(format!("Invalid timezone {}: {}", self.timezone, e)))?;
+
+        Ok(())
+    }
+}
+
+impl GroupConfig {
+    /// Validate group configuration against the configured websites
+    pub fn validate(&self, websites: &HashMap<String, WebsiteConfig>) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(AppError::Config("Group name cannot be empty".to_string()));
         }
 
-        // Validate email addresses
-        for recipient in &self.recipients {
-            if !recipient.contains('@') {
-                return Err(AppError::Config(format!(
-                    "Invalid email address: {recipient}"
-                )));
-            }
+        if self.websites.is_empty() {
+            return Err(AppError::Config(format!(
+                "Group {} has no websites",
+                self.name
+            )));
         }
 
-        // Validate timezone
+        if let Some(name) = self
+            .websites
+            .iter()
+            .find(|name| !websites.contains_key(*name))
+        {
+            return Err(AppError::Config(format!(
+                "Group {} refers to unknown website {name}",
+                self.name
+            )));
+        }
+
+        validate_recipients(&self.recipients)?;
+        validate_sections(&self.sections, &self.event_properties)?;
+
         self.timezone
             .parse::<chrono_tz::Tz>()
             .map_err(|e| AppError::Config(format!("Invalid timezone {}: {}", self.timezone, e)))?;
@@ -197,3 +670,50 @@ impl WebsiteConfig {
         Ok(())
     }
 }
+
+fn validate_recipients(recipients: &[String]) -> Result<()> {
+    if recipients.is_empty() {
+        return Err(AppError::Config(
+            "At least one recipient is required".to_string(),
+        ));
+    }
+
+    // Validate email addresses
+    for recipient in recipients {
+        if !recipient.contains('@') {
+            return Err(AppError::Config(format!(
+                "Invalid email address: {recipient}"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_sections(
+    sections: &[SectionConfig],
+    event_properties: &BTreeMap<String, Vec<String>>,
+) -> Result<()> {
+    for (i, section) in sections.iter().enumerate() {
+        if section.limit == Some(0) {
+            return Err(AppError::Config(format!(
+                "Section {:?} limit must be greater than 0",
+                section.kind
+            )));
+        }
+        if sections[..i].iter().any(|s| s.kind == section.kind) {
+            return Err(AppError::Config(format!(
+                "Section {:?} is configured more than once",
+                section.kind
+            )));
+        }
+    }
+
+    if !event_properties.is_empty() && !sections.iter().any(|s| s.kind == SectionKind::Events) {
+        return Err(AppError::Config(
+            "event_properties requires an events section".to_string(),
+        ));
+    }
+
+    Ok(())
+}
@@ -4,7 +4,10 @@ use tracing::warn;
 
 #[cfg(test)]
 use {
-    crate::config::models::{AppConfig, ReportType, SmtpConfig},
+    crate::config::models::{
+        AppConfig, DaemonConfig, ExporterConfig, GroupConfig, ReportType, SectionConfig,
+        SectionKind, SmtpConfig,
+    },
     crate::WebsiteConfig,
 };
 
@@ -19,16 +22,28 @@ pub fn validate_config(config: &Config) -> Result<()> {
 
     let mut has_enabled_websites = false;
     for (name, website) in &config.websites {
-        if website.disabled {
+        // Disabled websites are still used by the groups they are in
+        if website.disabled && !config.is_grouped(name) {
             warn!("Website {} is disabled", name);
             continue;
         }
-        has_enabled_websites = true;
+        has_enabled_websites |= !website.disabled;
         website.validate()?;
     }
 
-    if !has_enabled_websites {
-        return Err(AppError::Config("No enabled websites found".to_string()));
+    if !has_enabled_websites && config.groups.is_empty() {
+        return Err(AppError::Config(
+            "No enabled websites or groups found".to_string(),
+        ));
+    }
+
+    for (name, group) in &config.groups {
+        if config.websites.contains_key(name) {
+            return Err(AppError::Config(format!(
+                "Group {name} has the same name as a website"
+            )));
+        }
+        group.validate(&config.websites)?;
     }
 
     // Validate max concurrent jobs
@@ -71,16 +86,29 @@ mod tests {
                         recipients: vec!["admin@example.com".to_string()],
                         timezone: "UTC".to_string(),
                         disabled: false,
+                        comparison: Default::default(),
+                        sections: vec![SectionConfig {
+                            kind: SectionKind::Pages,
+                            limit: None,
+                        }],
+                        event_properties: Default::default(),
                     },
                 );
                 map
             },
+            groups: HashMap::new(),
             app: AppConfig {
                 debug: false,
                 dry_run: false,
+                digest: false,
                 max_concurrent_jobs: 4,
                 report_type: ReportType::Daily,
+                data_dir: None,
+                max_catch_up_periods: 7,
+                force: false,
             },
+            daemon: DaemonConfig::default(),
+            exporter: ExporterConfig::default(),
         }
     }
 
@@ -110,4 +138,93 @@ mod tests {
         config.websites.get_mut("test").unwrap().timezone = "Invalid/Timezone".to_string();
         assert!(validate_config(&config).is_err());
     }
+
+    #[test]
+    fn test_section_validation() {
+        let mut config = create_test_config();
+        let website = config.websites.get_mut("test").unwrap();
+        website.sections.push(SectionConfig {
+            kind: SectionKind::Pages,
+            limit: Some(20),
+        });
+        assert!(validate_config(&config).is_err());
+
+        let website = config.websites.get_mut("test").unwrap();
+        website.sections[1].kind = SectionKind::Referrers;
+        assert!(validate_config(&config).is_ok());
+
+        config.websites.get_mut("test").unwrap().sections[1].limit = Some(0);
+        assert!(validate_config(&config).is_err());
+    }
+
+    fn test_group() -> GroupConfig {
+        GroupConfig {
+            name: "All Sites".to_string(),
+            websites: vec!["test".to_string()],
+            recipients: vec!["leads@example.com".to_string()],
+            timezone: "UTC".to_string(),
+            comparison: Default::default(),
+            sections: Vec::new(),
+            event_properties: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_group_validation() {
+        let mut config = create_test_config();
+        config.groups.insert("all".to_string(), test_group());
+        assert!(validate_config(&config).is_ok());
+
+        config.groups.get_mut("all").unwrap().websites[0] = "missing".to_string();
+        assert!(validate_config(&config).is_err());
+
+        config.groups.clear();
+        config.groups.insert("test".to_string(), test_group());
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_select_websites() {
+        let mut config = create_test_config();
+        let mut other = config.websites["test"].clone();
+        other.name = "Other Site".to_string();
+        config.websites.insert("other".to_string(), other);
+
+        config.groups.insert("all".to_string(), test_group());
+
+        let mut selected = config.clone();
+        selected
+            .select_websites(&["other".to_string()], &[])
+            .unwrap();
+        assert_eq!(selected.websites.keys().collect::<Vec<_>>(), ["other"]);
+        assert!(selected.groups.is_empty());
+
+        let mut excluded = config.clone();
+        excluded
+            .select_websites(&[], &["other".to_string()])
+            .unwrap();
+        assert_eq!(excluded.websites.keys().collect::<Vec<_>>(), ["test"]);
+        assert!(excluded.groups.contains_key("all"));
+
+        assert!(config
+            .select_websites(&["missing".to_string()], &[])
+            .is_err());
+
+        // A named group keeps its websites for its report, without reporting
+        // on them separately
+        let mut group = config.clone();
+        group.select_websites(&["all".to_string()], &[]).unwrap();
+        assert!(group.groups.contains_key("all"));
+        assert_eq!(group.websites.keys().collect::<Vec<_>>(), ["test"]);
+        assert_eq!(group.enabled_websites().count(), 0);
+        assert_eq!(group.report_websites().count(), 1);
+        assert!(validate_config(&group).is_ok());
+        // and its websites are still validated
+        group.websites.get_mut("test").unwrap().username = String::new();
+        assert!(validate_config(&group).is_err());
+
+        config.websites.get_mut("other").unwrap().disabled = true;
+        assert!(config.clone().select_websites(&[], &[]).is_ok());
+        assert!(config.select_websites(&["other".to_string()], &[]).is_err());
+    }
 }
@@ -0,0 +1,106 @@
+use lettre::{
+    message::{header, Attachment, Mailbox, Message, MultiPart, SinglePart},
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+        response::Response,
+    },
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::{
+    config::models::SmtpConfig,
+    error::{AppError, Result},
+    metrics,
+};
+
+/// An image embedded in an HTML email, referenced as `cid:<content_id>`
+#[derive(Debug, Clone)]
+pub struct InlineImage {
+    pub content_id: String,
+    pub png: Vec<u8>,
+}
+
+/// SMTP transport and sender address built from the `[smtp]` configuration
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn new(config: &SmtpConfig) -> Result<Self> {
+        let creds = Credentials::new(config.username.clone(), config.password.clone());
+
+        let tls_parameters = if config.tls {
+            let tls_params = if config.skip_tls_verify {
+                TlsParameters::builder(config.host.clone())
+                    .dangerous_accept_invalid_certs(true)
+                    .build()?
+            } else {
+                TlsParameters::new(config.host.clone())?
+            };
+            Tls::Required(tls_params)
+        } else {
+            Tls::None
+        };
+
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?
+            .credentials(creds)
+            .port(config.port)
+            .tls(tls_parameters)
+            .timeout(Some(Duration::from_secs(config.timeout_seconds)))
+            .build();
+
+        let from = config.from.parse()?;
+
+        Ok(Self { transport, from })
+    }
+
+    /// Connect, greet and authenticate against the server without sending any mail
+    pub async fn test_connection(&self) -> Result<()> {
+        debug!("Testing SMTP connection");
+
+        match self.transport.test_connection().await? {
+            true => Ok(()),
+            false => Err(AppError::Smtp(
+                "connection was closed by the server".to_string(),
+            )),
+        }
+    }
+
+    /// Send an HTML email to a single recipient, with the images it embeds
+    pub async fn send_html(
+        &self,
+        recipient: &str,
+        subject: &str,
+        html_content: &str,
+        images: &[InlineImage],
+    ) -> Result<Response> {
+        let html = SinglePart::builder()
+            .header(header::ContentType::TEXT_HTML)
+            .body(html_content.to_string());
+        let png = header::ContentType::parse("image/png")
+            .map_err(|e| AppError::Email(format!("Invalid content type: {e}")))?;
+        let body = images
+            .iter()
+            .fold(MultiPart::related().singlepart(html), |body, image| {
+                body.singlepart(
+                    Attachment::new_inline(image.content_id.clone())
+                        .body(image.png.clone(), png.clone()),
+                )
+            });
+
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(recipient.parse()?)
+            .subject(subject)
+            .multipart(MultiPart::alternative().multipart(body))?;
+
+        let started = Instant::now();
+        let result = self.transport.send(email).await;
+        metrics::observe_smtp_send(started.elapsed());
+        result.map_err(|e| AppError::Smtp(format!("Failed to send email to {recipient}: {e}")))
+    }
+}
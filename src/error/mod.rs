@@ -8,6 +8,8 @@ pub enum AppError {
     Io(std::io::Error),
     Config(String),
     Api(String),
+    /// The API rejected the token, which has to be renewed by logging in again
+    Unauthorized,
     Template(String),
     Smtp(String),
     Json(serde_json::Error),
@@ -32,6 +34,7 @@ impl StdError for AppError {
             // String-based variants don't have a source
             Self::Config(_)
             | Self::Api(_)
+            | Self::Unauthorized
             | Self::Template(_)
             | Self::Smtp(_)
             | Self::Email(_)
@@ -46,6 +49,7 @@ impl fmt::Display for AppError {
             Self::Io(e) => write!(f, "IO error: {e}"),
             Self::Config(msg) => write!(f, "Configuration error: {msg}"),
             Self::Api(msg) => write!(f, "API error: {msg}"),
+            Self::Unauthorized => write!(f, "API error: Authentication token expired or invalid"),
             Self::Template(msg) => write!(f, "Template error: {msg}"),
             Self::Smtp(msg) => write!(f, "SMTP error: {msg}"),
             Self::Json(e) => write!(f, "JSON error: {e}"),
@@ -120,6 +124,12 @@ impl From<lettre::address::AddressError> for AppError {
     }
 }
 
+impl From<png::EncodingError> for AppError {
+    fn from(err: png::EncodingError) -> Self {
+        Self::Template(format!("Failed to encode image: {err}"))
+    }
+}
+
 impl From<&str> for AppError {
     fn from(msg: &str) -> Self {
         Self::Task(msg.to_string())
@@ -0,0 +1,376 @@
+use chrono::{Duration, Utc};
+use futures::{
+    future,
+    stream::{self, StreamExt},
+};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{family::Family, gauge::Gauge},
+    registry::{Registry, Unit},
+};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, RwLock};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, error, info};
+
+use crate::{
+    api::{
+        models::{Metric, MetricType, Stats},
+        UmamiClient,
+    },
+    config::{self, models::ExporterConfig, Config, WebsiteConfig},
+    error::{AppError, Result},
+    metrics,
+};
+
+type FloatGauge = Gauge<f64, AtomicU64>;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct WebsiteLabels {
+    website: String,
+    website_id: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct MetricLabels {
+    website: String,
+    website_id: String,
+    metric: &'static str,
+    value: String,
+}
+
+/// The stats and top metrics of a website over the exporter window
+struct WebsiteData {
+    stats: Stats,
+    metrics: Vec<(MetricType, Vec<Metric>)>,
+}
+
+/// The outcome of refreshing a website
+struct Snapshot<'a> {
+    website: &'a WebsiteConfig,
+    data: Option<WebsiteData>,
+}
+
+/// A client logged in to the Umami instance of a website
+#[derive(Clone)]
+struct Session {
+    client: UmamiClient,
+    token: String,
+}
+
+/// The session of each website by name, kept across refreshes so that the
+/// exporter only logs in again once Umami rejects a token
+#[derive(Default)]
+struct Sessions(Mutex<HashMap<String, Session>>);
+
+impl Sessions {
+    /// The kept session of a website, logging in if there is none
+    async fn get(&self, name: &str, website: &WebsiteConfig) -> Result<Session> {
+        if let Some(session) = self.0.lock().await.get(name) {
+            return Ok(session.clone());
+        }
+
+        let client = UmamiClient::new(website.base_url.clone())?;
+        let token = client
+            .authenticate(&website.username, &website.password)
+            .await?;
+        let session = Session { client, token };
+        self.0
+            .lock()
+            .await
+            .insert(name.to_string(), session.clone());
+        Ok(session)
+    }
+
+    async fn forget(&self, name: &str) {
+        self.0.lock().await.remove(name);
+    }
+}
+
+/// Periodically fetch the stats and top metrics of every enabled website and
+/// serve the latest values as Prometheus gauges
+pub async fn run(config: &Config) -> Result<()> {
+    let exporter = &config.exporter;
+    if exporter.interval_seconds == 0 {
+        return Err(AppError::config(
+            "The exporter interval must be at least a second",
+        ));
+    }
+    config::load_country_map().await?;
+
+    let body = Arc::new(RwLock::new(String::from("# EOF\n")));
+    let listener = TcpListener::bind(exporter.listen).await?;
+    info!(
+        "Exporting Umami stats on http://{}/metrics",
+        exporter.listen
+    );
+    let served = Arc::clone(&body);
+    let mut server = tokio::spawn(metrics::serve(listener, move || {
+        Ok(served.read().map(|body| body.clone()).unwrap_or_default())
+    }));
+
+    let sessions = Sessions::default();
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    let mut interval = time::interval(std::time::Duration::from_secs(exporter.interval_seconds));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                // A slow Umami instance doesn't hold up shutting down
+                let text = tokio::select! {
+                    result = collect(config, &sessions) => result,
+                    _ = &mut shutdown => {
+                        info!("Shutting down, abandoning the refresh in progress");
+                        return Ok(());
+                    }
+                };
+                match text {
+                    Ok(text) => {
+                        if let Ok(mut body) = body.write() {
+                            *body = text;
+                        }
+                    }
+                    Err(e) => error!("Failed to export stats: {}", e),
+                }
+            }
+            result = &mut server => {
+                return result.map_err(|e| AppError::task(format!("Exporter server stopped: {e}")))?;
+            }
+            _ = &mut shutdown => {
+                info!("Shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Fetch every enabled website and encode the results
+async fn collect(config: &Config, sessions: &Sessions) -> Result<String> {
+    let snapshots = stream::iter(config.enabled_websites())
+        .map(|(name, website)| async move {
+            let data = match fetch(&config.exporter, sessions, name, website).await {
+                Ok(data) => Some(data),
+                Err(e) => {
+                    error!("Failed to export stats of {}: {}", name, e);
+                    None
+                }
+            };
+            Snapshot { website, data }
+        })
+        .buffer_unordered(config.app.max_concurrent_jobs.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    debug!("Exported stats of {} websites", snapshots.len());
+    render(&config.exporter, &snapshots)
+}
+
+/// Fetch a website with its kept session, logging in again once if the token
+/// was rejected
+async fn fetch(
+    exporter: &ExporterConfig,
+    sessions: &Sessions,
+    name: &str,
+    website: &WebsiteConfig,
+) -> Result<WebsiteData> {
+    let end = Utc::now();
+    let start = end - Duration::minutes(exporter.window_minutes as i64);
+    let (start, end) = (start.timestamp_millis(), end.timestamp_millis());
+
+    let session = sessions.get(name, website).await?;
+    match fetch_with(exporter, &session, name, website, start, end).await {
+        Err(AppError::Unauthorized) => {
+            debug!("Token of {} was rejected, logging in again", name);
+            sessions.forget(name).await;
+            let session = sessions.get(name, website).await?;
+            fetch_with(exporter, &session, name, website, start, end).await
+        }
+        result => result,
+    }
+}
+
+/// Fetch the stats and top metrics of a website. A metric type that fails is
+/// left out, the website only fails without its stats.
+async fn fetch_with(
+    exporter: &ExporterConfig,
+    session: &Session,
+    name: &str,
+    website: &WebsiteConfig,
+    start: i64,
+    end: i64,
+) -> Result<WebsiteData> {
+    let Session { client, token } = session;
+    let (stats, metrics) = tokio::join!(
+        client.get_stats(token, &website.id, start, end),
+        future::join_all(exporter.metrics.iter().map(|&metric_type| async move {
+            let result = client
+                .get_metrics(token, &website.id, metric_type, start, end, exporter.limit)
+                .await;
+            (metric_type, result)
+        })),
+    );
+    let stats = stats?;
+
+    let metrics = metrics
+        .into_iter()
+        .filter_map(|(metric_type, result)| match result {
+            Ok(items) => Some((metric_type, items)),
+            Err(e) => {
+                error!("Failed to export the {} of {}: {}", metric_type, name, e);
+                None
+            }
+        })
+        .collect();
+    Ok(WebsiteData { stats, metrics })
+}
+
+/// Encode the snapshots in the OpenMetrics text format. Websites that could
+/// not be fetched only report `umami_up` as 0.
+fn render(exporter: &ExporterConfig, snapshots: &[Snapshot]) -> Result<String> {
+    let mut registry = Registry::with_prefix("umami");
+    let window = format!("over the last {} minutes", exporter.window_minutes);
+
+    let up = Family::<WebsiteLabels, FloatGauge>::default();
+    registry.register(
+        "up",
+        "Whether the last refresh of the website succeeded",
+        up.clone(),
+    );
+
+    let stats = [
+        ("pageviews", "Pageviews", None),
+        ("visitors", "Unique visitors", None),
+        ("visits", "Visits", None),
+        ("bounces", "Visits that viewed a single page", None),
+        ("time_on_site", "Total time spent", Some(Unit::Seconds)),
+    ]
+    .map(|(name, help, unit)| {
+        let family = Family::<WebsiteLabels, FloatGauge>::default();
+        let help = format!("{help} {window}");
+        match unit {
+            Some(unit) => registry.register_with_unit(name, help, unit, family.clone()),
+            None => registry.register(name, help, family.clone()),
+        }
+        family
+    });
+
+    let top = Family::<MetricLabels, FloatGauge>::default();
+    registry.register(
+        "metric",
+        format!("Top values of each exported metric {window}"),
+        top.clone(),
+    );
+
+    for snapshot in snapshots {
+        let labels = WebsiteLabels {
+            website: snapshot.website.name.clone(),
+            website_id: snapshot.website.id.clone(),
+        };
+        let Some(data) = &snapshot.data else {
+            up.get_or_create(&labels).set(0.0);
+            continue;
+        };
+        up.get_or_create(&labels).set(1.0);
+
+        let values = [
+            data.stats.pageviews,
+            data.stats.visitors,
+            data.stats.visits,
+            data.stats.bounces,
+            data.stats.totaltime,
+        ];
+        for (family, value) in stats.iter().zip(values) {
+            family.get_or_create(&labels).set(value);
+        }
+
+        for (metric_type, items) in &data.metrics {
+            for item in items {
+                top.get_or_create(&MetricLabels {
+                    website: labels.website.clone(),
+                    website_id: labels.website_id.clone(),
+                    metric: metric_type.as_str(),
+                    value: item.x.clone(),
+                })
+                .set(item.y);
+            }
+        }
+    }
+
+    let mut body = String::new();
+    encode(&mut body, &registry)
+        .map_err(|e| AppError::Task(format!("Failed to encode metrics: {e}")))?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::models::Comparison;
+    use std::collections::BTreeMap;
+
+    fn website(id: &str, name: &str) -> WebsiteConfig {
+        WebsiteConfig {
+            id: id.to_string(),
+            name: name.to_string(),
+            base_url: "https://analytics.example.com".to_string(),
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+            recipients: Vec::new(),
+            timezone: "UTC".to_string(),
+            disabled: false,
+            comparison: Comparison::default(),
+            sections: Vec::new(),
+            event_properties: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let up = website("site-1", "example.com");
+        let down = website("site-2", "example.org");
+        let snapshots = [
+            Snapshot {
+                website: &up,
+                data: Some(WebsiteData {
+                    stats: Stats {
+                        pageviews: 120.0,
+                        visitors: 40.0,
+                        visits: 50.0,
+                        bounces: 20.0,
+                        totaltime: 3600.0,
+                        ..Default::default()
+                    },
+                    metrics: vec![(
+                        MetricType::Browser,
+                        vec![Metric {
+                            x: "Firefox".to_string(),
+                            y: 31.0,
+                        }],
+                    )],
+                }),
+            },
+            Snapshot {
+                website: &down,
+                data: None,
+            },
+        ];
+
+        let body = render(&ExporterConfig::default(), &snapshots).unwrap();
+        assert!(body.contains(r#"umami_up{website="example.com",website_id="site-1"} 1.0"#));
+        assert!(body.contains(r#"umami_up{website="example.org",website_id="site-2"} 0.0"#));
+        assert!(
+            body.contains(r#"umami_pageviews{website="example.com",website_id="site-1"} 120.0"#)
+        );
+        assert!(body.contains(
+            r#"umami_time_on_site_seconds{website="example.com",website_id="site-1"} 3600.0"#
+        ));
+        assert!(body.contains(
+            r#"umami_metric{website="example.com",website_id="site-1",metric="browser",value="Firefox"} 31.0"#
+        ));
+        assert!(!body.contains(r#"umami_pageviews{website="example.org""#));
+    }
+}
@@ -0,0 +1,327 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use super::{append_line, read_lines};
+use crate::{
+    error::{AppError, Result},
+    report::models::ReportData,
+};
+
+/// Name of the ledger file inside the data directory
+pub const LEDGER_FILE: &str = "ledger.jsonl";
+
+/// How a report reached a recipient
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    /// An email with the report of a single website or group
+    Email,
+    /// A digest email with the reports of several websites
+    Digest,
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Email => "email",
+            Channel::Digest => "digest",
+        }
+    }
+}
+
+/// Identifies a report of one period sent to one recipient
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct DeliveryKey {
+    pub website_id: String,
+    pub report_type: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub recipient: String,
+    pub channel: Channel,
+}
+
+impl DeliveryKey {
+    pub fn new(report: &ReportData, recipient: &str, channel: Channel) -> Self {
+        Self {
+            website_id: report.website_id.clone(),
+            report_type: report.report_type.clone(),
+            start: report.start,
+            end: report.end,
+            recipient: recipient.to_string(),
+            channel,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Sent,
+    Failed,
+    /// Generated but not sent, as dry runs don't send email
+    DryRun,
+    /// Not sent, as the report could not be generated
+    NotGenerated,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "FAILED",
+            DeliveryStatus::DryRun => "dry run",
+            DeliveryStatus::NotGenerated => "NOT GENERATED",
+        }
+    }
+
+    /// Whether the report did not reach the recipient because of an error
+    pub fn is_failure(&self) -> bool {
+        matches!(self, DeliveryStatus::Failed | DeliveryStatus::NotGenerated)
+    }
+}
+
+/// An attempt at sending a report to a recipient
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Delivery {
+    #[serde(flatten)]
+    pub key: DeliveryKey,
+    pub website_name: String,
+    pub period: String,
+    pub status: DeliveryStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+    pub duration_ms: u64,
+}
+
+impl Delivery {
+    pub fn new(key: DeliveryKey, report: &ReportData, result: &Result<()>, took: Duration) -> Self {
+        let (status, error) = match result {
+            Ok(()) => (DeliveryStatus::Sent, None),
+            Err(e) => (DeliveryStatus::Failed, Some(e.to_string())),
+        };
+        Self {
+            key,
+            website_name: report.website_name.clone(),
+            period: report.date.clone(),
+            status,
+            error,
+            attempted_at: Utc::now(),
+            duration_ms: took.as_millis() as u64,
+        }
+    }
+
+    /// A report of a dry run, which is never sent
+    pub fn dry_run(key: DeliveryKey, report: &ReportData) -> Self {
+        Self {
+            key,
+            website_name: report.website_name.clone(),
+            period: report.date.clone(),
+            status: DeliveryStatus::DryRun,
+            error: None,
+            attempted_at: Utc::now(),
+            duration_ms: 0,
+        }
+    }
+
+    /// A report that failed to generate after the given time, so was not sent
+    pub fn not_generated(
+        key: DeliveryKey,
+        website_name: &str,
+        period: &str,
+        error: &AppError,
+        took: Duration,
+    ) -> Self {
+        Self {
+            key,
+            website_name: website_name.to_string(),
+            period: period.to_string(),
+            status: DeliveryStatus::NotGenerated,
+            error: Some(error.to_string()),
+            attempted_at: Utc::now(),
+            duration_ms: took.as_millis() as u64,
+        }
+    }
+}
+
+/// Every attempt at delivering a report, appended as JSON lines to a file, so
+/// that reports already sent for a period are not sent again
+pub struct Ledger {
+    path: PathBuf,
+    deliveries: Mutex<Vec<Delivery>>,
+}
+
+impl Ledger {
+    /// Load the ledger file of a data directory
+    pub async fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(LEDGER_FILE);
+        let deliveries = read_lines(&path).await?;
+        debug!(
+            "Loaded {} deliveries from {}",
+            deliveries.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            path,
+            deliveries: Mutex::new(deliveries),
+        })
+    }
+
+    pub async fn record(&self, delivery: Delivery) -> Result<()> {
+        let mut deliveries = self.deliveries.lock().await;
+        append_line(&self.path, &delivery).await?;
+        deliveries.push(delivery);
+        Ok(())
+    }
+
+    /// Every recorded delivery attempt, oldest first
+    pub async fn deliveries(&self) -> Vec<Delivery> {
+        self.deliveries.lock().await.clone()
+    }
+
+    /// The end of the latest period of a report type that was sent to one of
+    /// the given recipients of a website over a channel. Deliveries to former
+    /// recipients or over the other channel don't count.
+    pub async fn last_delivered(
+        &self,
+        website_id: &str,
+        report_type: &str,
+        recipients: &[String],
+        channel: Channel,
+    ) -> Option<DateTime<Utc>> {
+        self.deliveries
+            .lock()
+            .await
+            .iter()
+            .filter(|delivery| {
+                delivery.status == DeliveryStatus::Sent
+                    && delivery.key.website_id == website_id
+                    && delivery.key.report_type == report_type
+                    && delivery.key.channel == channel
+                    && recipients.contains(&delivery.key.recipient)
+            })
+            .map(|delivery| delivery.key.end)
+            .max()
+    }
+
+    /// Whether the report was sent successfully before
+    pub async fn is_delivered(&self, key: &DeliveryKey) -> bool {
+        self.deliveries
+            .lock()
+            .await
+            .iter()
+            .any(|delivery| delivery.status == DeliveryStatus::Sent && delivery.key == *key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+
+    #[tokio::test]
+    async fn test_ledger() {
+        let dir = std::env::temp_dir().join(format!("umami-alerts-ledger-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let report = ReportData::sample();
+        let sent = DeliveryKey::new(&report, "a@example.com", Channel::Email);
+        let failed = DeliveryKey::new(&report, "b@example.com", Channel::Email);
+
+        let ledger = Ledger::open(&dir).await.unwrap();
+        ledger
+            .record(Delivery::new(
+                sent.clone(),
+                &report,
+                &Ok(()),
+                Duration::from_millis(120),
+            ))
+            .await
+            .unwrap();
+        ledger
+            .record(Delivery::new(
+                failed.clone(),
+                &report,
+                &Err(AppError::Smtp("connection refused".to_string())),
+                Duration::from_millis(30),
+            ))
+            .await
+            .unwrap();
+        assert!(ledger.is_delivered(&sent).await);
+        let recipients = ["a@example.com".to_string(), "b@example.com".to_string()];
+        assert_eq!(
+            ledger
+                .last_delivered("sample", "Daily", &recipients, Channel::Email)
+                .await,
+            Some(report.end)
+        );
+        assert_eq!(
+            ledger
+                .last_delivered("sample", "Monthly", &recipients, Channel::Email)
+                .await,
+            None
+        );
+        assert_eq!(
+            ledger
+                .last_delivered("sample", "Daily", &recipients, Channel::Digest)
+                .await,
+            None
+        );
+        assert_eq!(
+            ledger
+                .last_delivered("sample", "Daily", &recipients[1..], Channel::Email)
+                .await,
+            None
+        );
+
+        let dry_run = DeliveryKey::new(&report, "c@example.com", Channel::Email);
+        ledger
+            .record(Delivery::dry_run(dry_run.clone(), &report))
+            .await
+            .unwrap();
+        let not_generated = DeliveryKey::new(&report, "d@example.com", Channel::Email);
+        ledger
+            .record(Delivery::not_generated(
+                not_generated.clone(),
+                &report.website_name,
+                &report.date,
+                &AppError::api("Server returned 500"),
+                Duration::from_millis(80),
+            ))
+            .await
+            .unwrap();
+
+        let ledger = Ledger::open(&dir).await.unwrap();
+        assert!(ledger.is_delivered(&sent).await);
+        assert!(!ledger.is_delivered(&failed).await);
+        assert!(!ledger.is_delivered(&dry_run).await);
+        assert!(!ledger.is_delivered(&not_generated).await);
+        let statuses: Vec<_> = ledger
+            .deliveries()
+            .await
+            .iter()
+            .map(|delivery| delivery.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                DeliveryStatus::Sent,
+                DeliveryStatus::Failed,
+                DeliveryStatus::DryRun,
+                DeliveryStatus::NotGenerated
+            ]
+        );
+        assert!(
+            !ledger
+                .is_delivered(&DeliveryKey::new(&report, "a@example.com", Channel::Digest))
+                .await
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
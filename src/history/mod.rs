@@ -0,0 +1,298 @@
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::{
+    error::{AppError, Result},
+    report::{helpers, models::ReportData},
+};
+
+pub mod ledger;
+
+/// Name of the history file inside the data directory
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// Name of the file inside the data directory the full reports are kept in
+pub const REPORTS_FILE: &str = "reports.jsonl";
+
+/// Number of prior periods shown in a report
+pub const HISTORY_DEPTH: usize = 6;
+
+/// What happened to a generated report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SendStatus {
+    Sent,
+    DryRun,
+    Failed,
+}
+
+/// A generated report as kept in the history file, without the full report
+/// data stored alongside it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub website_id: String,
+    pub website_name: String,
+    pub report_type: String,
+    pub period: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    pub status: SendStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub pageviews: f64,
+    pub visitors: f64,
+    pub visits: f64,
+    pub bounce_rate: f64,
+}
+
+impl HistoryEntry {
+    pub fn new(report: &ReportData, status: SendStatus, error: Option<String>) -> Self {
+        Self {
+            website_id: report.website_id.clone(),
+            website_name: report.website_name.clone(),
+            report_type: report.report_type.clone(),
+            period: report.date.clone(),
+            start: report.start,
+            end: report.end,
+            generated_at: Utc::now(),
+            status,
+            error,
+            pageviews: report.stats.pageviews,
+            visitors: report.stats.visitors,
+            visits: report.stats.visits,
+            bounce_rate: helpers::bounce_rate(report.stats.bounces, report.stats.visits),
+        }
+    }
+}
+
+/// A line of the reports file
+#[derive(Serialize)]
+struct HistoryRecord<'a> {
+    #[serde(flatten)]
+    entry: &'a HistoryEntry,
+    #[serde(serialize_with = "serialize_without_history")]
+    report: &'a ReportData,
+}
+
+/// Serialize a report without the earlier reports listed in it, as those are
+/// already in the history file
+fn serialize_without_history<S: serde::Serializer>(
+    report: &&ReportData,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    let mut value = serde_json::to_value(report).map_err(serde::ser::Error::custom)?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("history");
+    }
+    value.serialize(serializer)
+}
+
+/// Every report generated so far. The entries are appended as JSON lines to
+/// the history file, which is loaded once and kept in memory. The full reports
+/// are appended to a separate file that is only written.
+pub struct HistoryStore {
+    path: PathBuf,
+    reports_path: PathBuf,
+    entries: Mutex<Vec<HistoryEntry>>,
+}
+
+impl HistoryStore {
+    /// Load the history file of a data directory
+    pub async fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(HISTORY_FILE);
+        let entries = read_lines(&path).await?;
+        debug!(
+            "Loaded {} history entries from {}",
+            entries.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            path,
+            reports_path: dir.join(REPORTS_FILE),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Record a generated report and what happened to it
+    pub async fn record(&self, report: &ReportData, entry: HistoryEntry) -> Result<()> {
+        let mut entries = self.entries.lock().await;
+        append_line(
+            &self.reports_path,
+            &HistoryRecord {
+                entry: &entry,
+                report,
+            },
+        )
+        .await?;
+        append_line(&self.path, &entry).await?;
+        entries.push(entry);
+        Ok(())
+    }
+
+    /// The latest entry of each period of the same website and report type
+    /// that ended before the given time, most recent first
+    pub async fn previous(
+        &self,
+        website_id: &str,
+        report_type: &str,
+        before: DateTime<Utc>,
+    ) -> Vec<HistoryEntry> {
+        previous(&self.entries.lock().await, website_id, report_type, before)
+    }
+}
+
+/// Read every line of a JSON lines file, skipping lines that can't be parsed.
+/// A missing file has no lines.
+async fn read_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let content = match fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(number, line)| match serde_json::from_str(line) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!("Skipping line {} of {}: {}", number + 1, path.display(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Append a value as a line of a JSON lines file, creating the file and its
+/// directory if needed
+async fn append_line<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|e| AppError::Task(format!("Failed to open {}: {e}", path.display())))?;
+    file.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+fn previous(
+    entries: &[HistoryEntry],
+    website_id: &str,
+    report_type: &str,
+    before: DateTime<Utc>,
+) -> Vec<HistoryEntry> {
+    let mut periods: BTreeMap<DateTime<Utc>, &HistoryEntry> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| {
+        entry.website_id == website_id && entry.report_type == report_type && entry.end < before
+    }) {
+        // Entries are in the order they were recorded, so later ones win
+        periods.insert(entry.start, entry);
+    }
+
+    periods
+        .into_values()
+        .rev()
+        .take(HISTORY_DEPTH)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::models::ReportData;
+    use chrono::{Duration, TimeZone};
+
+    fn report_for_day(day: u32, pageviews: f64) -> ReportData {
+        let mut report = ReportData::sample();
+        report.start = Utc.with_ymd_and_hms(2025, 1, day, 0, 0, 0).unwrap();
+        report.end = report.start + Duration::days(1) - Duration::seconds(1);
+        report.stats.pageviews = pageviews;
+        report
+    }
+
+    #[test]
+    fn test_previous() {
+        let entries: Vec<_> = (1..=9)
+            .map(|day| HistoryEntry::new(&report_for_day(day, day as f64), SendStatus::Sent, None))
+            .chain([HistoryEntry::new(
+                &report_for_day(3, 30.0),
+                SendStatus::DryRun,
+                None,
+            )])
+            .collect();
+        let before = Utc.with_ymd_and_hms(2025, 1, 9, 0, 0, 0).unwrap();
+
+        let history = previous(&entries, "sample", "Daily", before);
+        assert_eq!(history.len(), HISTORY_DEPTH);
+        assert_eq!(history[0].pageviews, 8.0);
+        assert_eq!(history[5].pageviews, 30.0);
+        assert_eq!(history[5].status, SendStatus::DryRun);
+
+        assert!(previous(&entries, "sample", "Weekly", before).is_empty());
+        assert!(previous(&entries, "other", "Daily", before).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_store() {
+        let dir = std::env::temp_dir().join(format!("umami-alerts-history-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let store = HistoryStore::open(&dir).await.unwrap();
+        for day in 1..=2 {
+            let report = report_for_day(day, day as f64);
+            let entry = HistoryEntry::new(&report, SendStatus::Sent, None);
+            store.record(&report, entry).await.unwrap();
+        }
+        let report = report_for_day(3, 3.0);
+        let entry = HistoryEntry::new(&report, SendStatus::Failed, Some("refused".into()));
+        store.record(&report, entry).await.unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join(HISTORY_FILE))
+            .and_then(|mut file| std::io::Write::write_all(&mut file, b"not json\n"))
+            .unwrap();
+
+        let store = HistoryStore::open(&dir).await.unwrap();
+        let history = store
+            .previous(
+                "sample",
+                "Daily",
+                Utc.with_ymd_and_hms(2025, 2, 1, 0, 0, 0).unwrap(),
+            )
+            .await;
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].status, SendStatus::Failed);
+        assert_eq!(history[0].error.as_deref(), Some("refused"));
+
+        let content = std::fs::read_to_string(dir.join(HISTORY_FILE)).unwrap();
+        let line: serde_json::Value =
+            serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert!(line.get("report").is_none());
+
+        let content = std::fs::read_to_string(dir.join(REPORTS_FILE)).unwrap();
+        let line: serde_json::Value =
+            serde_json::from_str(content.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(line["period"], line["report"]["date"]);
+        assert_eq!(line["report"]["website_id"], "sample");
+        assert!(line["report"].get("history").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,20 +1,45 @@
-use clap::Parser;
-use futures::stream::{self, StreamExt};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches, Parser, Subcommand};
+use futures::{
+    future,
+    stream::{self, StreamExt},
+};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
 use tokio::sync::Semaphore;
+use tokio::time::{self, MissedTickBehavior};
 use tracing::{debug, error, info, Level};
 
 mod api;
+mod commands;
 mod config;
+mod email;
 mod error;
+mod exporter;
+mod history;
+mod metrics;
 mod report;
 
 use crate::{
     api::UmamiClient,
-    config::{validation::validate_config, Config, WebsiteConfig},
+    config::{
+        models::{GroupConfig, ReportType},
+        validation::validate_config,
+        Config, WebsiteConfig,
+    },
     error::{AppError, Result},
-    report::generator::ReportGenerator,
+    history::{
+        ledger::{Channel, Ledger},
+        HistoryStore, SendStatus,
+    },
+    report::{
+        generator::{ReportGenerator, ReportSubject, Source},
+        models::ReportData,
+        period::{self, ReportWindow},
+    },
 };
 
 #[derive(Clone)]
@@ -22,6 +47,21 @@ struct AppState {
     config: Arc<Config>,
     report_generator: Arc<ReportGenerator>,
     semaphore: Arc<Semaphore>,
+    windows: Arc<Vec<ReportWindow>>,
+}
+
+/// The reports generated for a website or group, collected for digests, and
+/// the combined error of the windows that failed
+#[derive(Default)]
+struct Generated {
+    reports: Vec<ReportData>,
+    error: Option<AppError>,
+}
+
+/// A report to generate for each window: of a website, or of a group of them
+enum Job<'a> {
+    Website(&'a WebsiteConfig),
+    Group(&'a GroupConfig),
 }
 
 #[derive(Parser, Debug)]
@@ -30,21 +70,150 @@ struct Args {
     /// Path to the configuration file
     #[arg(short, long, default_value = "config.toml")]
     config: PathBuf,
+
+    /// Only process this website (the name of its `[websites.<name>]` section); repeatable
+    #[arg(long = "site", value_name = "NAME", global = true)]
+    sites: Vec<String>,
+
+    /// Skip this website; repeatable
+    #[arg(long, value_name = "NAME", global = true)]
+    exclude: Vec<String>,
+
+    /// Report on the period containing this date instead of the latest complete one
+    #[arg(long, value_name = "YYYY-MM-DD", conflicts_with_all = ["from", "to"])]
+    date: Option<NaiveDate>,
+
+    /// First day of a custom report range
+    #[arg(long, value_name = "YYYY-MM-DD", requires = "to")]
+    from: Option<NaiveDate>,
+
+    /// Last day of a custom report range
+    #[arg(long, value_name = "YYYY-MM-DD", requires = "from")]
+    to: Option<NaiveDate>,
+
+    /// Override the report type from the configuration
+    #[arg(long, value_enum, global = true)]
+    report_type: Option<ReportType>,
+
+    /// Generate reports without sending any email
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Send each recipient one digest email for all of their websites
+    #[arg(long, global = true)]
+    digest: bool,
+
+    /// Send reports again to recipients that already received them
+    #[arg(long, global = true)]
+    force: bool,
+
+    /// Send reports to this address instead of the configured recipients; repeatable.
+    /// Not named `--to`, which ends the `--from`/`--to` range.
+    #[arg(long = "recipient", value_name = "EMAIL", global = true)]
+    recipients: Vec<String>,
+
+    /// Enable debug logging
+    #[arg(long, global = true)]
+    debug: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    /// Parse the command line. `--date`, `--from` and `--to` only apply to the
+    /// default run, and clap cannot declare a conflict between arguments and
+    /// subcommands, so they are rejected here rather than ignored.
+    fn parse_checked() -> Self {
+        let matches = Args::command().get_matches();
+        let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if let Some((name, _)) = matches.subcommand() {
+            let flag = match (args.date, args.from) {
+                (Some(_), _) => Some("--date"),
+                (_, Some(_)) => Some("--from/--to"),
+                _ => None,
+            };
+            if let Some(flag) = flag {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("{flag} cannot be used with the {name} subcommand"),
+                    )
+                    .exit();
+            }
+        }
+        args
+    }
+
+    /// Apply command line overrides on top of the loaded configuration
+    fn apply_overrides(&self, config: &mut Config) {
+        if let Some(report_type) = self.report_type {
+            config.app.report_type = report_type;
+        }
+        config.app.dry_run |= self.dry_run;
+        config.app.digest |= self.digest;
+        config.app.force |= self.force;
+        config.app.debug |= self.debug;
+
+        if !self.recipients.is_empty() {
+            for website in config.websites.values_mut() {
+                website.recipients = self.recipients.clone();
+            }
+            for group in config.groups.values_mut() {
+                group.recipients = self.recipients.clone();
+            }
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Verify the configuration, Umami API access and SMTP login without sending reports
+    Check,
+    /// Send a report rendered from sample data to verify email delivery
+    SendTest {
+        /// Address to send the test report to
+        #[arg(long)]
+        to: String,
+    },
+    /// Generate one report for every complete period between two dates
+    Backfill {
+        /// First day of the span to backfill
+        #[arg(long, value_name = "YYYY-MM-DD")]
+        from: NaiveDate,
+        /// Last day of the span to backfill
+        #[arg(long, value_name = "YYYY-MM-DD")]
+        to: NaiveDate,
+    },
+    /// Keep running, sending the latest reports every `interval_minutes` and
+    /// serving Prometheus metrics
+    Daemon,
+    /// Keep running, serving the stats and top metrics of every website as
+    /// Prometheus gauges instead of sending reports
+    Exporter,
+    /// List past report deliveries with their outcome, error and duration,
+    /// including dry runs and reports that could not be generated
+    History {
+        /// Only list periods ending on or after this day
+        #[arg(long, value_name = "YYYY-MM-DD")]
+        from: Option<NaiveDate>,
+        /// Only list periods starting on or before this day
+        #[arg(long, value_name = "YYYY-MM-DD")]
+        to: Option<NaiveDate>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let args = Args::parse_checked();
 
     // Check if config file exists
     if !args.config.exists() {
         return Err(format!("Config file not found: {}", args.config.display()).into());
     }
     // Load configuration
-    let config = Config::load(&args.config).await?;
-    validate_config(&config)
-        .map_err(|e| AppError::api(format!("Config validation failed: {e}")))?;
-    let max_concurrent_jobs = config.app.max_concurrent_jobs;
+    let mut config = Config::load(&args.config).await?;
+    args.apply_overrides(&mut config);
 
     let log_level = if config.app.debug {
         Level::DEBUG
@@ -63,62 +232,217 @@ async fn main() -> Result<()> {
 
     info!("Starting umami-alerts");
     debug!("Debug mode enabled");
+
+    config.select_websites(&args.sites, &args.exclude)?;
+
+    match args.command {
+        Some(Command::Check) => commands::check::run(&config).await,
+        Some(Command::SendTest { to }) => commands::send_test::run(&config, &to).await,
+        Some(Command::Daemon) => run_daemon(config).await,
+        Some(Command::Exporter) => exporter::run(&config).await,
+        Some(Command::History { from, to }) => commands::history::run(&config, from, to).await,
+        Some(Command::Backfill { from, to }) => {
+            period::check_date_span(from, to)?;
+            let windows = period::backfill_anchors(&config.app.report_type, from, to)
+                .into_iter()
+                .map(ReportWindow::Containing)
+                .collect::<Vec<_>>();
+            if windows.is_empty() {
+                return Err(AppError::config(format!(
+                    "No complete {} period between {from} and {to}",
+                    config.app.report_type.to_string().to_lowercase()
+                )));
+            }
+            info!("Backfilling {} reports per website", windows.len());
+            check_windows(&config, &windows)?;
+            let report_generator = Arc::new(report_generator(&config).await?);
+            run_reports(Arc::new(config), report_generator, windows).await
+        }
+        None => {
+            let window = match (args.date, args.from, args.to) {
+                (Some(date), _, _) => ReportWindow::Containing(date),
+                (_, Some(from), Some(to)) => {
+                    period::check_date_span(from, to)?;
+                    ReportWindow::Custom { from, to }
+                }
+                _ => ReportWindow::Latest,
+            };
+            check_windows(&config, &[window])?;
+            let report_generator = Arc::new(report_generator(&config).await?);
+            run_reports(Arc::new(config), report_generator, vec![window]).await
+        }
+    }
+}
+
+/// Check that every window has ended in the timezones of all websites and
+/// groups before sending anything, rather than failing some of the reports
+fn check_windows(config: &Config, windows: &[ReportWindow]) -> Result<()> {
+    let timezones = config
+        .enabled_websites()
+        .map(|(_, website)| &website.timezone)
+        .chain(config.groups.values().map(|group| &group.timezone));
+    for timezone in timezones {
+        for window in windows {
+            period::calculate_time_range(timezone, &config.app.report_type, window)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the reports of the latest period on an interval, serving the metrics in
+/// the background. A failed run is logged and retried on the next interval.
+async fn run_daemon(config: Config) -> Result<()> {
+    if config.app.data_dir.is_none() {
+        return Err(AppError::config(
+            "The daemon needs a data_dir to keep track of the reports it sent",
+        ));
+    }
+    if config.daemon.interval_minutes == 0 {
+        return Err(AppError::config(
+            "The daemon interval must be at least a minute",
+        ));
+    }
+
+    // The stores are loaded once and kept up to date by every run
+    let report_generator = Arc::new(report_generator(&config).await?);
+    let config = Arc::new(config);
+
+    let listener = TcpListener::bind(config.daemon.listen).await?;
+    info!("Serving metrics on http://{}/metrics", config.daemon.listen);
+    let mut server = tokio::spawn(metrics::serve(listener, metrics::render));
+
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    let mut interval = time::interval(Duration::from_secs(config.daemon.interval_minutes * 60));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let run = run_reports(
+                    config.clone(),
+                    report_generator.clone(),
+                    vec![ReportWindow::Latest],
+                );
+                // A run can take long, so shutting down doesn't wait for it
+                tokio::select! {
+                    result = run => {
+                        if let Err(e) = result {
+                            error!("Report run failed: {}", e);
+                        }
+                    }
+                    _ = &mut shutdown => {
+                        info!("Shutting down, abandoning the report run in progress");
+                        return Ok(());
+                    }
+                }
+            }
+            result = &mut server => {
+                return result.map_err(|e| AppError::task(format!("Metrics server stopped: {e}")))?;
+            }
+            _ = &mut shutdown => {
+                info!("Shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Check the configuration and set up the template engine and the stores of
+/// the data directory, which every report run shares
+async fn report_generator(config: &Config) -> Result<ReportGenerator> {
+    validate_config(config).map_err(|e| AppError::api(format!("Config validation failed: {e}")))?;
+
     debug!("Report type: {:?}", config.app.report_type);
 
     config::load_country_map().await?;
     info!("Loaded country mappings");
 
     // Initialize template engine
-    let mut handlebars = handlebars::Handlebars::new();
-    handlebars.register_template_string(
-        "email",
-        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/email.html")),
-    )?;
-    handlebars.register_helper("formatNumber", Box::new(report::helpers::format_number));
-    handlebars.register_helper("percentage", Box::new(report::helpers::percentage));
-    handlebars.register_helper("formatFloat", Box::new(report::helpers::format_float));
-    handlebars.set_strict_mode(false);
-    handlebars::handlebars_helper!(sub: |x:f64, y:f64| x - y);
-    handlebars.register_helper("sub", Box::new(sub));
-    let handlebars = Arc::new(handlebars);
+    let handlebars = Arc::new(report::template_registry()?);
+
+    let (history, ledger) = match &config.app.data_dir {
+        Some(dir) => (
+            Some(Arc::new(HistoryStore::open(dir).await?)),
+            Some(Arc::new(Ledger::open(dir).await?)),
+        ),
+        None => (None, None),
+    };
+
+    Ok(ReportGenerator::new(handlebars, history, ledger))
+}
+
+async fn run_reports(
+    config: Arc<Config>,
+    report_generator: Arc<ReportGenerator>,
+    windows: Vec<ReportWindow>,
+) -> Result<()> {
+    let max_concurrent_jobs = config.app.max_concurrent_jobs;
 
     // Create application state
     let state = AppState {
-        config: Arc::new(config),
-        report_generator: Arc::new(ReportGenerator::new(handlebars)),
+        config,
+        report_generator,
         semaphore: Arc::new(Semaphore::new(max_concurrent_jobs)),
+        windows: Arc::new(windows),
     };
 
-    // Process all enabled websites concurrently
-    let results = stream::iter(state.config.enabled_websites())
-        .map(|(name, website)| {
+    // Process all enabled websites and groups concurrently
+    let jobs = state
+        .config
+        .enabled_websites()
+        .map(|(name, website)| (name, Job::Website(website)))
+        .chain(
+            state
+                .config
+                .groups
+                .iter()
+                .map(|(name, group)| (name, Job::Group(group))),
+        );
+    let results = stream::iter(jobs)
+        .map(|(name, job)| {
             let state = state.clone();
             async move {
                 let _permit = state.semaphore.acquire().await.unwrap();
-                match process_website(&state, name, website).await {
-                    Ok(_) => Ok(name.to_string()),
-                    Err(e) => Err((name.to_string(), e)),
-                }
+                let result = match job {
+                    Job::Website(website) => process_website(&state, name, website).await,
+                    Job::Group(group) => process_group(&state, name, group).await,
+                };
+                let generated = result.unwrap_or_else(|e| Generated {
+                    reports: Vec::new(),
+                    error: Some(e),
+                });
+                (name.as_str(), generated)
             }
         })
         .buffer_unordered(max_concurrent_jobs)
         .collect::<Vec<_>>()
         .await;
 
-    // Report results
-    let (successes, failures): (Vec<_>, Vec<_>) = results.into_iter().partition(|r| r.is_ok());
+    // Reports of websites that failed some windows still go into the digests
+    if state.config.app.digest {
+        let reports: Vec<_> = results
+            .iter()
+            .map(|(name, generated)| (*name, generated.reports.as_slice()))
+            .collect();
+        send_digests(&state, &reports).await?;
+    }
 
+    // Report results
+    let failures: Vec<_> = results
+        .iter()
+        .filter_map(|(name, generated)| Some((*name, generated.error.as_ref()?)))
+        .collect();
     info!(
         "Processing complete. {} succeeded, {} failed",
-        successes.len(),
+        results.len() - failures.len(),
         failures.len()
     );
 
     if !failures.is_empty() {
-        let failed_sites: Vec<_> = failures
-            .iter()
-            .map(|r| r.as_ref().unwrap_err().0.clone())
-            .collect();
+        for (name, e) in &failures {
+            error!("Failed to process {}: {}", name, e);
+        }
+        let failed_sites: Vec<_> = failures.iter().map(|(name, _)| *name).collect();
         error!("Failed websites: {}", failed_sites.join(", "));
         return Err(AppError::task(format!(
             "Failed to process {} websites",
@@ -129,29 +453,231 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn process_website(state: &AppState, site_name: &str, website: &WebsiteConfig) -> Result<()> {
+/// Group the generated reports by recipient and period, and send each group
+/// as one digest email, oldest period first
+async fn send_digests(state: &AppState, reports: &[(&str, &[ReportData])]) -> Result<()> {
+    // Reports of the same period go together, even when the websites are in
+    // different timezones or some caught up on more periods than others
+    let mut digests: BTreeMap<(&str, &str), Vec<&ReportData>> = BTreeMap::new();
+    for (name, site_reports) in reports {
+        let recipients = match state.config.websites.get(*name) {
+            Some(website) => &website.recipients,
+            None => &state.config.groups[*name].recipients,
+        };
+        for report in *site_reports {
+            for recipient in recipients {
+                digests
+                    .entry((&report.date, recipient))
+                    .or_default()
+                    .push(report);
+            }
+        }
+    }
+    let mut digests: Vec<_> = digests.into_iter().collect();
+    digests.sort_by_key(|(_, reports)| reports.iter().map(|report| report.start).min());
+
+    // A report is recorded as failed if any digest it was part of failed
+    let mut failed: HashMap<(&str, DateTime<Utc>), String> = HashMap::new();
+    let mut first_error = None;
+    for ((_, recipient), mut reports) in digests {
+        reports.sort_by(|a, b| a.website_name.cmp(&b.website_name));
+        if let Err(e) = state
+            .report_generator
+            .send_digest(&state.config, recipient, reports.clone())
+            .await
+        {
+            error!("Failed to send the digest to {}: {}", recipient, e);
+            for report in &reports {
+                failed
+                    .entry((&report.website_id, report.start))
+                    .or_insert_with(|| e.to_string());
+            }
+            first_error.get_or_insert(e);
+        }
+    }
+
+    for report in reports.iter().flat_map(|(_, site_reports)| *site_reports) {
+        let (status, error) = match failed.get(&(report.website_id.as_str(), report.start)) {
+            Some(e) => (SendStatus::Failed, Some(e.clone())),
+            None if state.config.app.dry_run => (SendStatus::DryRun, None),
+            None => (SendStatus::Sent, None),
+        };
+        state.report_generator.record(report, status, error).await;
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
+async fn process_website(
+    state: &AppState,
+    site_name: &str,
+    website: &WebsiteConfig,
+) -> Result<Generated> {
     info!("Processing website: {}", site_name);
 
     // Create API client
     let client = UmamiClient::new(website.base_url.clone())?;
 
     // Authenticate
-    let token = client
+    let started = Instant::now();
+    let token = match client
         .authenticate(&website.username, &website.password)
-        .await?;
+        .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            let subject = ReportSubject::website(website, &client, "");
+            record_login_failure(state, &subject, &e, started.elapsed()).await;
+            return Err(e);
+        }
+    };
 
-    // Generate and send report
-    state
+    let subject = ReportSubject::website(website, &client, &token);
+    generate_reports(state, &subject).await
+}
+
+/// Generate the combined reports of a group of websites, authenticating with
+/// each of them
+async fn process_group(
+    state: &AppState,
+    group_name: &str,
+    group: &GroupConfig,
+) -> Result<Generated> {
+    info!("Processing group: {}", group_name);
+
+    let started = Instant::now();
+    let members = match future::try_join_all(group.websites.iter().map(|name| async move {
+        let website = &state.config.websites[name];
+        let client = UmamiClient::new(website.base_url.clone())?;
+        let token = client
+            .authenticate(&website.username, &website.password)
+            .await?;
+        Ok::<_, AppError>((website, client, token))
+    }))
+    .await
+    {
+        Ok(members) => members,
+        Err(e) => {
+            let subject = ReportSubject::group(group_name, group, Vec::new());
+            record_login_failure(state, &subject, &e, started.elapsed()).await;
+            return Err(e);
+        }
+    };
+
+    let sources = members
+        .iter()
+        .map(|(website, client, token)| Source {
+            name: &website.name,
+            website_id: &website.id,
+            client,
+            token,
+        })
+        .collect();
+    let subject = ReportSubject::group(group_name, group, sources);
+    generate_reports(state, &subject).await
+}
+
+/// Record the reports of a subject as not generated when logging in to Umami
+/// failed, so that the history lists them
+async fn record_login_failure(
+    state: &AppState,
+    subject: &ReportSubject<'_>,
+    error: &AppError,
+    took: Duration,
+) {
+    let Ok(windows) = report_windows(state, subject).await else {
+        return;
+    };
+    for window in &windows {
+        state
+            .report_generator
+            .record_not_generated(&state.config, subject, window, error, took)
+            .await;
+    }
+}
+
+/// The windows to generate the reports of a subject for, oldest first.
+/// Regular runs first catch up on the periods missed since the last sent
+/// report.
+async fn report_windows(
+    state: &AppState,
+    subject: &ReportSubject<'_>,
+) -> Result<Vec<ReportWindow>> {
+    let mut windows = Vec::new();
+    if state.windows.as_slice() == [ReportWindow::Latest] {
+        windows = state
+            .report_generator
+            .missed_windows(&state.config, subject)
+            .await?;
+    }
+    windows.extend(state.windows.iter().copied());
+    Ok(windows)
+}
+
+/// Generate a report for each requested window, oldest first, sending each
+/// right away unless they are collected into digests. A failed window does not
+/// stop the later ones, so missed periods never hold up the latest report.
+async fn generate_reports(state: &AppState, subject: &ReportSubject<'_>) -> Result<Generated> {
+    let windows = report_windows(state, subject).await?;
+
+    let mut generated = Generated::default();
+    let mut errors = Vec::new();
+    for window in &windows {
+        match generate_window(state, subject, window).await {
+            Ok(Some(report)) => generated.reports.push(report),
+            Ok(None) => {}
+            Err(e) => {
+                let period = period::calculate_time_range(
+                    subject.timezone,
+                    &state.config.app.report_type,
+                    window,
+                )
+                .map_or_else(|_| format!("{window:?}"), |range| range.label);
+                error!("Failed the {} report for {}: {}", period, subject.name, e);
+                errors.push(format!("{period}: {e}"));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        generated.error = Some(AppError::task(format!(
+            "{} of {} reports failed: {}",
+            errors.len(),
+            windows.len(),
+            errors.join("; ")
+        )));
+    }
+    Ok(generated)
+}
+
+/// Generate the report of one window, returning it if it goes into a digest
+async fn generate_window(
+    state: &AppState,
+    subject: &ReportSubject<'_>,
+    window: &ReportWindow,
+) -> Result<Option<ReportData>> {
+    if !state.config.app.digest {
+        return state
+            .report_generator
+            .generate_and_send(&state.config, subject, window)
+            .await
+            .map(|_| None);
+    }
+
+    let recipients = state
         .report_generator
-        .generate_and_send(
-            &client,
-            &state.config.app.dry_run,
-            website,
-            &state.config.app.report_type,
-            &state.config.smtp,
-            &token,
-        )
+        .pending_recipients(&state.config, subject, window, Channel::Digest)
         .await?;
-
-    Ok(())
+    if recipients.is_empty() {
+        info!(
+            "Report for {} was already sent in every digest, skipping",
+            subject.name
+        );
+        return Ok(None);
+    }
+    state
+        .report_generator
+        .generate(&state.config, subject, window)
+        .await
+        .map(Some)
 }
@@ -0,0 +1,184 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::{Registry, Unit},
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::error;
+
+use crate::{
+    error::{AppError, Result},
+    history::ledger::Channel,
+};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// What happened to a report, as counted by the reports metric
+#[derive(Debug, Clone, Copy)]
+pub enum ReportStatus {
+    Generated,
+    Sent,
+    Failed,
+}
+
+impl ReportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportStatus::Generated => "generated",
+            ReportStatus::Sent => "sent",
+            ReportStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ReportLabels {
+    website: String,
+    channel: &'static str,
+    status: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct EndpointLabels {
+    endpoint: &'static str,
+}
+
+/// The metrics of the process, recorded in every mode and served by the daemon
+struct Metrics {
+    registry: Registry,
+    reports: Family<ReportLabels, Counter>,
+    api_requests: Family<EndpointLabels, Histogram>,
+    smtp_sends: Histogram,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let mut registry = Registry::with_prefix("umami_alerts");
+
+        let reports = Family::<ReportLabels, Counter>::default();
+        registry.register(
+            "reports",
+            "Reports generated, and sent or failed per recipient",
+            reports.clone(),
+        );
+
+        let api_requests = Family::<EndpointLabels, Histogram>::new_with_constructor(|| {
+            Histogram::new(exponential_buckets(0.01, 2.0, 12))
+        });
+        registry.register_with_unit(
+            "umami_api_request_duration",
+            "Latency of Umami API requests",
+            Unit::Seconds,
+            api_requests.clone(),
+        );
+
+        let smtp_sends = Histogram::new(exponential_buckets(0.05, 2.0, 10));
+        registry.register_with_unit(
+            "smtp_send_duration",
+            "Latency of sending an email over SMTP",
+            Unit::Seconds,
+            smtp_sends.clone(),
+        );
+
+        Self {
+            registry,
+            reports,
+            api_requests,
+            smtp_sends,
+        }
+    }
+}
+
+pub fn count_report(website: &str, channel: Channel, status: ReportStatus) {
+    METRICS
+        .reports
+        .get_or_create(&ReportLabels {
+            website: website.to_string(),
+            channel: channel.as_str(),
+            status: status.as_str(),
+        })
+        .inc();
+}
+
+pub fn observe_api_request(endpoint: &'static str, duration: Duration) {
+    METRICS
+        .api_requests
+        .get_or_create(&EndpointLabels { endpoint })
+        .observe(duration.as_secs_f64());
+}
+
+pub fn observe_smtp_send(duration: Duration) {
+    METRICS.smtp_sends.observe(duration.as_secs_f64());
+}
+
+/// All metrics in the OpenMetrics text format
+pub fn render() -> Result<String> {
+    let mut body = String::new();
+    encode(&mut body, &METRICS.registry)
+        .map_err(|e| AppError::Task(format!("Failed to encode metrics: {e}")))?;
+    Ok(body)
+}
+
+/// Serve the text returned by `render` on `/metrics` until the server fails
+pub async fn serve<F>(listener: TcpListener, render: F) -> Result<()>
+where
+    F: Fn() -> Result<String> + Clone + Send + Sync + 'static,
+{
+    let app = Router::new().route(
+        "/metrics",
+        get(move || {
+            let render = render.clone();
+            async move { respond(render()) }
+        }),
+    );
+    axum::serve(listener, app)
+        .await
+        .map_err(|e| AppError::Task(format!("Metrics server failed: {e}")))
+}
+
+fn respond(body: Result<String>) -> Response {
+    match body {
+        Ok(body) => ([(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response(),
+        Err(e) => {
+            error!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        count_report("metrics-test.com", Channel::Email, ReportStatus::Sent);
+        count_report("metrics-test.com", Channel::Email, ReportStatus::Sent);
+        observe_api_request("metrics_test", Duration::from_millis(30));
+        observe_smtp_send(Duration::from_millis(200));
+
+        let body = render().unwrap();
+        assert!(body.contains(
+            r#"umami_alerts_reports_total{website="metrics-test.com",channel="email",status="sent"} 2"#
+        ));
+        assert!(body.contains(
+            r#"umami_alerts_umami_api_request_duration_seconds_bucket{le="0.04",endpoint="metrics_test"} 1"#
+        ));
+        assert!(body.contains("umami_alerts_smtp_send_duration_seconds_count 1"));
+        assert!(body.ends_with("# EOF\n"));
+    }
+}
@@ -0,0 +1,318 @@
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, Timelike, Weekday};
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::period::TimeRange;
+use crate::api::models::{PageviewSeries, SeriesPoint, TimeUnit};
+
+/// Height of the tallest bar of the traffic chart, in pixels
+const CHART_HEIGHT: f64 = 80.0;
+
+/// Pageviews and sessions over the report window, drawn as table cells so
+/// that the chart shows up in mail clients without loading images
+#[derive(Debug, Serialize)]
+pub struct TrafficChart {
+    pub unit: TimeUnit,
+    /// The highest pageview count of any bucket
+    pub peak: f64,
+    pub bars: Vec<ChartBar>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChartBar {
+    /// Short label shown below the bar
+    pub label: String,
+    /// Full description of the bucket, shown on hover
+    pub title: String,
+    pub pageviews: f64,
+    pub sessions: f64,
+    /// Height of the sessions bar, in pixels
+    pub sessions_height: u32,
+    /// Height of the part of the pageviews bar above the sessions bar, in pixels
+    pub pageviews_height: u32,
+}
+
+impl TrafficChart {
+    /// Build a chart with one bar per bucket of the range, including buckets
+    /// that Umami left out for having no traffic
+    pub fn new(tz: &Tz, range: &TimeRange, unit: TimeUnit, series: &PageviewSeries) -> Self {
+        let pageviews = bucket_values(tz, unit, &series.pageviews);
+        let sessions = bucket_values(tz, unit, &series.sessions);
+        let buckets = buckets(tz, range, unit);
+        let peak = buckets
+            .iter()
+            .filter_map(|bucket| pageviews.get(bucket))
+            .fold(0.0, |peak: f64, &y| peak.max(y));
+
+        let height = |value: f64| {
+            if peak > 0.0 {
+                (value / peak * CHART_HEIGHT).round() as u32
+            } else {
+                0
+            }
+        };
+
+        let bars = buckets
+            .iter()
+            .map(|bucket| {
+                let pageviews = pageviews.get(bucket).copied().unwrap_or(0.0);
+                let sessions = sessions.get(bucket).copied().unwrap_or(0.0);
+                let sessions_height = height(sessions.min(pageviews));
+                ChartBar {
+                    label: bucket_label(bucket, unit, buckets.len()),
+                    title: bucket_title(bucket, unit),
+                    pageviews,
+                    sessions,
+                    sessions_height,
+                    pageviews_height: height(pageviews).saturating_sub(sessions_height),
+                }
+            })
+            .collect();
+
+        Self { unit, peak, bars }
+    }
+}
+
+/// Pageviews by day of the week and hour of the day, with a row for each
+/// weekday in the report window, starting on Monday
+#[derive(Debug, Serialize)]
+pub struct Heatmap {
+    pub weekdays: Vec<String>,
+    pub hours: Vec<String>,
+    /// Pageviews of each weekday by hour, in the website timezone
+    pub pageviews: Vec<Vec<f64>>,
+    /// The highest pageview count of any cell
+    pub peak: f64,
+}
+
+impl Heatmap {
+    /// Build the heatmap from an hourly pageview series
+    pub fn new(tz: &Tz, range: &TimeRange, series: &PageviewSeries) -> Self {
+        let mut weekdays: Vec<Weekday> = buckets(tz, range, TimeUnit::Day)
+            .iter()
+            .map(|day| day.weekday())
+            .collect();
+        weekdays.sort_by_key(|day| day.num_days_from_monday());
+        weekdays.dedup();
+
+        let mut pageviews = vec![vec![0.0; 24]; weekdays.len()];
+        for (time, y) in bucket_values(tz, TimeUnit::Hour, &series.pageviews) {
+            if time < truncate(range.start.with_timezone(tz).naive_local(), TimeUnit::Hour)
+                || time > range.end.with_timezone(tz).naive_local()
+            {
+                continue;
+            }
+            if let Some(row) = weekdays.iter().position(|day| *day == time.weekday()) {
+                pageviews[row][time.hour() as usize] += y;
+            }
+        }
+        let peak = pageviews
+            .iter()
+            .flatten()
+            .fold(0.0, |peak: f64, &y| peak.max(y));
+
+        Self {
+            weekdays: weekdays.iter().map(|day| day.to_string()).collect(),
+            hours: (0..24).map(|hour| format!("{hour:02}")).collect(),
+            pageviews,
+            peak,
+        }
+    }
+}
+
+/// The local start of every bucket of the given unit that overlaps the range
+pub fn buckets(tz: &Tz, range: &TimeRange, unit: TimeUnit) -> Vec<NaiveDateTime> {
+    let end = range.end.with_timezone(tz).naive_local();
+    let mut bucket = truncate(range.start.with_timezone(tz).naive_local(), unit);
+    let mut buckets = Vec::new();
+
+    while bucket <= end {
+        buckets.push(bucket);
+        bucket = match unit {
+            TimeUnit::Hour => bucket + Duration::hours(1),
+            TimeUnit::Day => bucket + Duration::days(1),
+            TimeUnit::Month => match bucket.checked_add_months(Months::new(1)) {
+                Some(next) => next,
+                None => break,
+            },
+        };
+    }
+
+    buckets
+}
+
+/// Sum the points of a series by the local bucket they fall into
+pub fn bucket_values(
+    tz: &Tz,
+    unit: TimeUnit,
+    points: &[SeriesPoint],
+) -> HashMap<NaiveDateTime, f64> {
+    let mut values = HashMap::new();
+    for point in points {
+        if let Some(time) = parse_bucket(tz, &point.x) {
+            *values.entry(truncate(time, unit)).or_insert(0.0) += point.y;
+        }
+    }
+    values
+}
+
+/// Parse the start of a time series bucket. Umami returns local times without
+/// an offset, although some versions return UTC timestamps instead.
+fn parse_bucket(tz: &Tz, x: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(x)
+                .ok()
+                .map(|time| time.with_timezone(tz).naive_local())
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(x, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+}
+
+fn truncate(time: NaiveDateTime, unit: TimeUnit) -> NaiveDateTime {
+    let date = match unit {
+        TimeUnit::Month => time.date().with_day(1).unwrap_or(time.date()),
+        _ => time.date(),
+    };
+    let hour = match unit {
+        TimeUnit::Hour => time.hour(),
+        _ => 0,
+    };
+    date.and_hms_opt(hour, 0, 0).unwrap_or(time)
+}
+
+fn bucket_label(bucket: &NaiveDateTime, unit: TimeUnit, count: usize) -> String {
+    match unit {
+        TimeUnit::Hour => bucket.format("%H").to_string(),
+        TimeUnit::Day if count <= 7 => bucket.format("%a").to_string(),
+        TimeUnit::Day => bucket.format("%-d").to_string(),
+        TimeUnit::Month => bucket.format("%b").to_string(),
+    }
+}
+
+fn bucket_title(bucket: &NaiveDateTime, unit: TimeUnit) -> String {
+    match unit {
+        TimeUnit::Hour => bucket.format("%B %d, %H:00").to_string(),
+        TimeUnit::Day => bucket.format("%A, %B %d").to_string(),
+        TimeUnit::Month => bucket.format("%B %Y").to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::models::ReportType;
+    use crate::report::period::{calculate_time_range, ReportWindow};
+
+    fn point(x: &str, y: f64) -> SeriesPoint {
+        SeriesPoint {
+            x: x.to_string(),
+            y,
+        }
+    }
+
+    #[test]
+    fn test_buckets() {
+        let tz: Tz = "Europe/Berlin".parse().unwrap();
+        let date = NaiveDate::from_ymd_opt(2025, 3, 12).unwrap();
+
+        let range = calculate_time_range(
+            "Europe/Berlin",
+            &ReportType::Daily,
+            &ReportWindow::Containing(date),
+        )
+        .unwrap();
+        assert_eq!(buckets(&tz, &range, TimeUnit::Hour).len(), 24);
+
+        let range = calculate_time_range(
+            "Europe/Berlin",
+            &ReportType::Monthly,
+            &ReportWindow::Containing(date),
+        )
+        .unwrap();
+        assert_eq!(buckets(&tz, &range, TimeUnit::Day).len(), 31);
+
+        let range = calculate_time_range(
+            "Europe/Berlin",
+            &ReportType::Yearly,
+            &ReportWindow::Containing(date),
+        )
+        .unwrap();
+        let months = buckets(&tz, &range, TimeUnit::Month);
+        assert_eq!(months.len(), 12);
+        assert_eq!(months[11].month(), 12);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let tz: Tz = "Europe/Berlin".parse().unwrap();
+        let range = calculate_time_range(
+            "Europe/Berlin",
+            &ReportType::IsoWeekly,
+            &ReportWindow::Containing(NaiveDate::from_ymd_opt(2025, 1, 8).unwrap()),
+        )
+        .unwrap();
+        let series = PageviewSeries {
+            pageviews: vec![
+                point("2025-01-06 09:00:00", 12.0),
+                point("2025-01-12 23:00:00", 4.0),
+                point("2025-01-08T13:00:00Z", 7.0),
+            ],
+            sessions: Vec::new(),
+        };
+
+        let heatmap = Heatmap::new(&tz, &range, &series);
+        assert_eq!(heatmap.weekdays.len(), 7);
+        assert_eq!(heatmap.weekdays[0], "Mon");
+        assert_eq!(heatmap.pageviews[0][9], 12.0);
+        assert_eq!(heatmap.pageviews[6][23], 4.0);
+        assert_eq!(heatmap.pageviews[2][14], 7.0);
+        assert_eq!(heatmap.peak, 12.0);
+
+        let range = calculate_time_range(
+            "Europe/Berlin",
+            &ReportType::Daily,
+            &ReportWindow::Containing(NaiveDate::from_ymd_opt(2025, 1, 12).unwrap()),
+        )
+        .unwrap();
+        let heatmap = Heatmap::new(&tz, &range, &series);
+        assert_eq!(heatmap.weekdays, ["Sun"]);
+        assert_eq!(heatmap.peak, 4.0);
+    }
+
+    #[test]
+    fn test_traffic_chart() {
+        let tz: Tz = "Asia/Kolkata".parse().unwrap();
+        let range = calculate_time_range(
+            "Asia/Kolkata",
+            &ReportType::Weekly,
+            &ReportWindow::Custom {
+                from: NaiveDate::from_ymd_opt(2025, 1, 6).unwrap(),
+                to: NaiveDate::from_ymd_opt(2025, 1, 8).unwrap(),
+            },
+        )
+        .unwrap();
+        let series = PageviewSeries {
+            pageviews: vec![
+                point("2025-01-06 00:00:00", 40.0),
+                point("2025-01-08T10:00:00Z", 20.0),
+            ],
+            sessions: vec![point("2025-01-06 00:00:00", 10.0)],
+        };
+
+        let chart = TrafficChart::new(&tz, &range, TimeUnit::Day, &series);
+        assert_eq!(chart.peak, 40.0);
+        assert_eq!(chart.bars.len(), 3);
+        assert_eq!(chart.bars[0].label, "Mon");
+        assert_eq!(chart.bars[0].sessions_height, 20);
+        assert_eq!(chart.bars[0].pageviews_height, 60);
+        assert_eq!(chart.bars[1].pageviews, 0.0);
+        assert_eq!(chart.bars[2].pageviews, 20.0);
+        assert_eq!(chart.bars[2].pageviews_height, 40);
+    }
+}
@@ -1,225 +1,619 @@
-use chrono::{DateTime, Datelike, TimeZone, Utc};
-use handlebars::Handlebars;
-use lettre::{
-    message::{header, Message, MultiPart},
-    transport::smtp::{
-        authentication::Credentials,
-        client::{Tls, TlsParameters},
-    },
-    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+use futures::{
+    future,
+    stream::{self, StreamExt, TryStreamExt},
 };
+use handlebars::Handlebars;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info};
 
-use super::{helpers, models::ReportData};
+use super::{
+    chart::{self, Heatmap, TrafficChart},
+    helpers,
+    models::{
+        Baseline, Breakdown, Contribution, Digest, HeadlineChanges, RankedMetric, RankingTable,
+        ReportData, ReportSection,
+    },
+    period::{self, ReportWindow, TimeRange},
+    sparkline::{self, Sparklines},
+};
 use crate::{
     api::client::UmamiClient,
-    api::models::MetricValue,
-    config::models::{ReportType, SmtpConfig, WebsiteConfig},
+    api::models::{MetricType, MetricValue, PageviewSeries, Stats, TimeUnit},
+    config::models::{
+        Comparison, Config, GroupConfig, ReportType, SectionConfig, SectionKind, SmtpConfig,
+        WebsiteConfig,
+    },
+    email::{InlineImage, Mailer},
     error::{AppError, Result},
+    history::{
+        ledger::{Channel, Delivery, DeliveryKey, Ledger},
+        HistoryEntry, HistoryStore, SendStatus,
+    },
+    metrics::{self, ReportStatus},
 };
+use chrono::{DateTime, Utc};
+
+/// Number of items fetched for the comparison period of ranked metrics, and
+/// from each website of a group
+const COMPARISON_LIMIT: u32 = 100;
+
+/// Number of sparkline slices whose stats are fetched at the same time, each
+/// a request to every website of the subject
+const MAX_CONCURRENT_SLICES: usize = 3;
+
+/// A website queried for a report, with the client and token for its Umami
+/// instance
+pub struct Source<'a> {
+    pub name: &'a str,
+    pub website_id: &'a str,
+    pub client: &'a UmamiClient,
+    pub token: &'a str,
+}
+
+/// What a report covers: a single website, or a group of websites whose data
+/// is combined
+pub struct ReportSubject<'a> {
+    /// The Umami website id, or the name of the group
+    pub id: &'a str,
+    pub name: &'a str,
+    pub recipients: &'a [String],
+    pub timezone: &'a str,
+    pub comparison: &'a Comparison,
+    pub sections: &'a [SectionConfig],
+    pub event_properties: &'a BTreeMap<String, Vec<String>>,
+    pub sources: Vec<Source<'a>>,
+}
+
+impl<'a> ReportSubject<'a> {
+    pub fn website(website: &'a WebsiteConfig, client: &'a UmamiClient, token: &'a str) -> Self {
+        Self {
+            id: &website.id,
+            name: &website.name,
+            recipients: &website.recipients,
+            timezone: &website.timezone,
+            comparison: &website.comparison,
+            sections: &website.sections,
+            event_properties: &website.event_properties,
+            sources: vec![Source {
+                name: &website.name,
+                website_id: &website.id,
+                client,
+                token,
+            }],
+        }
+    }
 
-#[derive(Debug)]
-struct TimeRange {
-    start: DateTime<Utc>,
-    end: DateTime<Utc>,
-    label: String,
+    pub fn group(id: &'a str, group: &'a GroupConfig, sources: Vec<Source<'a>>) -> Self {
+        Self {
+            id,
+            name: &group.name,
+            recipients: &group.recipients,
+            timezone: &group.timezone,
+            comparison: &group.comparison,
+            sections: &group.sections,
+            event_properties: &group.event_properties,
+            sources,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ReportGenerator {
     template: Arc<Handlebars<'static>>,
+    history: Option<Arc<HistoryStore>>,
+    ledger: Option<Arc<Ledger>>,
+}
+
+/// The channel reports are delivered over with the configuration
+fn active_channel(config: &Config) -> Channel {
+    if config.app.digest {
+        Channel::Digest
+    } else {
+        Channel::Email
+    }
+}
+
+/// Identifies the report of a subject over a time range sent to a recipient
+fn window_key(
+    subject: &ReportSubject<'_>,
+    report_type: &str,
+    time_range: &TimeRange,
+    recipient: &str,
+    channel: Channel,
+) -> DeliveryKey {
+    DeliveryKey {
+        website_id: subject.id.to_string(),
+        report_type: report_type.to_string(),
+        start: time_range.start,
+        end: time_range.end,
+        recipient: recipient.to_string(),
+        channel,
+    }
+}
+
+/// An email ready to be sent to any number of recipients
+struct Message<'a> {
+    subject: String,
+    html: &'a str,
+    images: Vec<InlineImage>,
 }
 
 impl ReportGenerator {
-    pub fn new(template: Arc<Handlebars<'static>>) -> Self {
-        Self { template }
+    pub fn new(
+        template: Arc<Handlebars<'static>>,
+        history: Option<Arc<HistoryStore>>,
+        ledger: Option<Arc<Ledger>>,
+    ) -> Self {
+        Self {
+            template,
+            history,
+            ledger,
+        }
     }
 
     pub async fn generate_and_send(
         &self,
-        client: &UmamiClient,
-        dry_run: &bool,
-        website: &WebsiteConfig,
-        report_type: &ReportType,
-        smtp_config: &SmtpConfig,
-        token: &str,
+        config: &Config,
+        subject: &ReportSubject<'_>,
+        window: &ReportWindow,
     ) -> Result<()> {
-        info!("Generating report for website: {}", website.name);
-
-        let time_range = self.calculate_time_range(&website.timezone, report_type)?;
-        let report_data = self
-            .fetch_report_data(client, website, token, time_range, report_type)
+        let recipients = self
+            .pending_recipients(config, subject, window, Channel::Email)
             .await?;
-        let html = self.render_report(&report_data)?;
+        if recipients.is_empty() {
+            info!(
+                "Report for {} was already sent to every recipient, skipping",
+                subject.name
+            );
+            return Ok(());
+        }
 
-        if *dry_run {
+        let started = Instant::now();
+        let report_data = self.generate(config, subject, window).await?;
+        let html = match self.render_report(&report_data) {
+            Ok(html) => html,
+            Err(e) => {
+                self.record_not_generated(config, subject, window, &e, started.elapsed())
+                    .await;
+                return Err(e);
+            }
+        };
+
+        let result = if config.app.dry_run {
             info!("Dry run enabled, will not send an email");
+            self.record_dry_run(&recipients, &[&report_data], Channel::Email)
+                .await;
+            Ok(())
         } else {
-            self.send_email(
-                smtp_config,
-                &website.recipients,
-                &format!(
-                    "{} Analytics Report - {} - {}",
-                    report_type, website.name, report_data.date
-                ),
-                &html,
-            )
-            .await?;
+            self.send_report(&config.smtp, &recipients, subject, &report_data, &html)
+                .await
+        };
+        match &result {
+            Ok(()) if config.app.dry_run => {
+                self.record(&report_data, SendStatus::DryRun, None).await
+            }
+            Ok(()) => self.record(&report_data, SendStatus::Sent, None).await,
+            Err(e) => {
+                self.record(&report_data, SendStatus::Failed, Some(e.to_string()))
+                    .await
+            }
         }
+        result?;
 
-        info!("Successfully sent report for website: {}", website.name);
+        info!("Successfully sent report for: {}", subject.name);
         Ok(())
     }
 
-    fn calculate_time_range(&self, timezone: &str, report_type: &ReportType) -> Result<TimeRange> {
-        let tz: chrono_tz::Tz = timezone.parse().map_err(|e| {
-            error!("Invalid timezone {}: {}", timezone, e);
-            AppError::Config(format!("Invalid timezone: {e}"))
-        })?;
+    async fn send_report(
+        &self,
+        smtp: &SmtpConfig,
+        recipients: &[String],
+        subject: &ReportSubject<'_>,
+        report_data: &ReportData,
+        html: &str,
+    ) -> Result<()> {
+        let message = Message {
+            subject: format!(
+                "{} Analytics Report - {} - {}",
+                report_data.report_type, subject.name, report_data.date
+            ),
+            html,
+            images: report_data.sparklines.images(&report_data.website_id)?,
+        };
+        self.send_email(smtp, recipients, &message, &[report_data], Channel::Email)
+            .await
+    }
 
-        let now = Utc::now().with_timezone(&tz);
-        let yesterday = now - chrono::Duration::days(1);
+    /// The recipients of a subject that have not received its report for the
+    /// window over a channel yet. Everyone is pending when forced or when no
+    /// ledger is kept.
+    pub async fn pending_recipients(
+        &self,
+        config: &Config,
+        subject: &ReportSubject<'_>,
+        window: &ReportWindow,
+        channel: Channel,
+    ) -> Result<Vec<String>> {
+        let Some(ledger) = self.ledger.as_ref().filter(|_| !config.app.force) else {
+            return Ok(subject.recipients.to_vec());
+        };
 
-        let (start, end, label) = match report_type {
-            ReportType::Daily => {
+        let report_type = &config.app.report_type;
+        let time_range = period::calculate_time_range(subject.timezone, report_type, window)?;
+        let mut pending = Vec::new();
+        for recipient in subject.recipients {
+            let key = window_key(
+                subject,
+                &window.title(report_type),
+                &time_range,
+                recipient,
+                channel,
+            );
+            if ledger.is_delivered(&key).await {
                 debug!(
-                    "Calculating daily report for {}",
-                    yesterday.format("%Y-%m-%d")
+                    "{} report for {} was already sent to {}",
+                    time_range.label, subject.name, recipient
                 );
-                let start_local = tz
-                    .with_ymd_and_hms(
-                        yesterday.year(),
-                        yesterday.month(),
-                        yesterday.day(),
-                        0,
-                        0,
-                        0,
-                    )
-                    .unwrap();
-                let end_local =
-                    start_local + chrono::Duration::days(1) - chrono::Duration::seconds(1);
-                let label = yesterday.format("%B %d, %Y").to_string();
-                (
-                    start_local.with_timezone(&Utc),
-                    end_local.with_timezone(&Utc),
-                    label,
-                )
+            } else {
+                pending.push(recipient.clone());
             }
-            ReportType::Weekly => {
-                debug!(
-                    "Calculating weekly report ending: {}",
-                    yesterday.format("%Y-%m-%d")
-                );
-                let end_local = tz
-                    .with_ymd_and_hms(
-                        yesterday.year(),
-                        yesterday.month(),
-                        yesterday.day(),
-                        23,
-                        59,
-                        59,
-                    )
-                    .unwrap();
-                let start_local =
-                    end_local - chrono::Duration::days(7) + chrono::Duration::seconds(1);
-                let label = format!(
-                    "{} \u{2013} {}",
-                    start_local.format("%B %d"),
-                    end_local.format("%B %d, %Y")
-                );
-                (
-                    start_local.with_timezone(&Utc),
-                    end_local.with_timezone(&Utc),
-                    label,
-                )
+        }
+        Ok(pending)
+    }
+
+    /// Add a generated report and the outcome of sending it to the history,
+    /// if one is kept. Failing to write the history does not fail the report.
+    pub async fn record(&self, report: &ReportData, status: SendStatus, error: Option<String>) {
+        let Some(history) = &self.history else {
+            return;
+        };
+
+        let entry = HistoryEntry::new(report, status, error);
+        if let Err(e) = history.record(report, entry).await {
+            error!(
+                "Failed to record the {} report for {}: {}",
+                report.date, report.website_name, e
+            );
+        }
+    }
+
+    /// Add to the ledger, if one is kept, that the reports were not sent to
+    /// the recipients because of a dry run
+    async fn record_dry_run(
+        &self,
+        recipients: &[String],
+        reports: &[&ReportData],
+        channel: Channel,
+    ) {
+        let Some(ledger) = &self.ledger else {
+            return;
+        };
+
+        for recipient in recipients {
+            for report in reports {
+                let key = DeliveryKey::new(report, recipient, channel);
+                if let Err(e) = ledger.record(Delivery::dry_run(key, report)).await {
+                    error!("Failed to record the delivery to {}: {}", recipient, e);
+                }
             }
-            ReportType::Monthly => {
-                // Previous calendar month: first day 00:00:00 through last day 23:59:59
-                let first_of_this_month = tz
-                    .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
-                    .unwrap();
-                let last_of_prev_month = first_of_this_month - chrono::Duration::seconds(1);
-                let first_of_prev_month = tz
-                    .with_ymd_and_hms(
-                        last_of_prev_month.year(),
-                        last_of_prev_month.month(),
-                        1,
-                        0,
-                        0,
-                        0,
-                    )
-                    .unwrap();
-                let label = last_of_prev_month.format("%B %Y").to_string();
-                debug!("Calculating monthly report for {}", label);
-                (
-                    first_of_prev_month.with_timezone(&Utc),
-                    last_of_prev_month.with_timezone(&Utc),
-                    label,
+        }
+    }
+
+    /// Add to the ledger, if one is kept, that the report of a window could
+    /// not be generated for the recipients still waiting for it
+    pub async fn record_not_generated(
+        &self,
+        config: &Config,
+        subject: &ReportSubject<'_>,
+        window: &ReportWindow,
+        error: &AppError,
+        took: Duration,
+    ) {
+        let Some(ledger) = &self.ledger else {
+            return;
+        };
+        let channel = active_channel(config);
+        let report_type = &config.app.report_type;
+        let (Ok(time_range), Ok(recipients)) = (
+            period::calculate_time_range(subject.timezone, report_type, window),
+            self.pending_recipients(config, subject, window, channel)
+                .await,
+        ) else {
+            return;
+        };
+
+        for recipient in recipients {
+            let key = window_key(
+                subject,
+                &window.title(report_type),
+                &time_range,
+                &recipient,
+                channel,
+            );
+            let delivery =
+                Delivery::not_generated(key, subject.name, &time_range.label, error, took);
+            if let Err(e) = ledger.record(delivery).await {
+                error!("Failed to record the delivery to {}: {}", recipient, e);
+            }
+        }
+    }
+
+    /// Fetch the data for a report over a window without sending it
+    pub async fn generate(
+        &self,
+        config: &Config,
+        subject: &ReportSubject<'_>,
+        window: &ReportWindow,
+    ) -> Result<ReportData> {
+        info!("Generating report for: {}", subject.name);
+
+        let started = Instant::now();
+        let report_type = &config.app.report_type;
+        let time_range = period::calculate_time_range(subject.timezone, report_type, window)?;
+        let report_title = window.title(report_type);
+        let result = self
+            .fetch_report_data(subject, time_range, report_title, report_type, window)
+            .await;
+        let channel = active_channel(config);
+        let status = match result {
+            Ok(_) => ReportStatus::Generated,
+            Err(_) => ReportStatus::Failed,
+        };
+        metrics::count_report(subject.name, channel, status);
+        let mut report_data = match result {
+            Ok(report_data) => report_data,
+            Err(e) => {
+                self.record_not_generated(config, subject, window, &e, started.elapsed())
+                    .await;
+                return Err(e);
+            }
+        };
+
+        if let Some(history) = &self.history {
+            report_data.history = history
+                .previous(
+                    &report_data.website_id,
+                    &report_data.report_type,
+                    report_data.start,
                 )
+                .await;
+        }
+        Ok(report_data)
+    }
+
+    /// The windows of the periods missed since a report of the subject was
+    /// last sent, oldest first, so that downtime doesn't leave gaps
+    pub async fn missed_windows(
+        &self,
+        config: &Config,
+        subject: &ReportSubject<'_>,
+    ) -> Result<Vec<ReportWindow>> {
+        let report_type = &config.app.report_type;
+        let Some(ledger) = &self.ledger else {
+            return Ok(Vec::new());
+        };
+        if config.app.max_catch_up_periods == 0 || !report_type.has_adjacent_periods() {
+            return Ok(Vec::new());
+        }
+        let channel = active_channel(config);
+        let Some(last_end) = ledger
+            .last_delivered(
+                subject.id,
+                &report_type.to_string(),
+                subject.recipients,
+                channel,
+            )
+            .await
+        else {
+            return Ok(Vec::new());
+        };
+
+        let tz = period::parse_timezone(subject.timezone)?;
+        let latest =
+            period::calculate_time_range(subject.timezone, report_type, &ReportWindow::Latest)?;
+        let windows = period::missed_windows(
+            &tz,
+            report_type,
+            last_end,
+            latest.start,
+            config.app.max_catch_up_periods,
+        );
+        if !windows.is_empty() {
+            info!(
+                "Catching up on {} missed reports for {}",
+                windows.len(),
+                subject.name
+            );
+        }
+        Ok(windows)
+    }
+
+    /// Send a single email with the reports of all websites of a recipient
+    pub async fn send_digest(
+        &self,
+        config: &Config,
+        recipient: &str,
+        mut reports: Vec<&ReportData>,
+    ) -> Result<()> {
+        if let Some(ledger) = self.ledger.as_ref().filter(|_| !config.app.force) {
+            let mut pending = Vec::new();
+            for report in reports {
+                let key = DeliveryKey::new(report, recipient, Channel::Digest);
+                if !ledger.is_delivered(&key).await {
+                    pending.push(report);
+                }
             }
+            reports = pending;
+        }
+        if reports.is_empty() {
+            info!("Digest was already sent to {}, skipping", recipient);
+            return Ok(());
+        }
+
+        let digest = Digest::new(reports);
+        let html = self.template.render("digest", &digest).map_err(|e| {
+            error!("Failed to render digest template: {}", e);
+            AppError::Template(format!("Failed to render digest: {e}"))
+        })?;
+
+        if config.app.dry_run {
+            info!("Dry run enabled, will not send the digest to {}", recipient);
+            self.record_dry_run(&[recipient.to_string()], &digest.reports, Channel::Digest)
+                .await;
+            return Ok(());
+        }
+
+        let mut images = Vec::new();
+        for report in &digest.reports {
+            images.extend(report.sparklines.images(&report.website_id)?);
+        }
+        let message = Message {
+            subject: format!(
+                "{} Analytics Digest - {} - {}",
+                digest.report_type, digest.websites, digest.date
+            ),
+            html: &html,
+            images,
         };
+        self.send_email(
+            &config.smtp,
+            &[recipient.to_string()],
+            &message,
+            &digest.reports,
+            Channel::Digest,
+        )
+        .await?;
 
-        debug!("Time range: {} to {}", start, end);
-        Ok(TimeRange { start, end, label })
+        info!("Successfully sent digest to {}", recipient);
+        Ok(())
     }
 
     async fn fetch_report_data(
         &self,
-        client: &UmamiClient,
-        website: &WebsiteConfig,
-        token: &str,
+        subject: &ReportSubject<'_>,
         time_range: TimeRange,
+        report_title: String,
         report_type: &ReportType,
+        window: &ReportWindow,
     ) -> Result<ReportData> {
         debug!(
             "Fetching metrics for time range: {} to {}",
             time_range.start, time_range.end
         );
 
-        let start_at = time_range.start.timestamp_millis();
-        let end_at = time_range.end.timestamp_millis();
+        let baseline_range =
+            period::baseline_range(subject.timezone, &time_range, subject.comparison)?;
+        let compare_range =
+            period::comparison_range(subject.timezone, &time_range, subject.comparison)?;
+        let query = ReportQuery {
+            subject,
+            range: &time_range,
+            compare_range: &compare_range,
+        };
+
+        let baseline_stats = async {
+            match &baseline_range {
+                Some(range) => query.stats(range.start, range.end).await.map(Some),
+                None => Ok(None),
+            }
+        };
+
+        // Daily reports already fetch an hourly series for the chart
+        let unit = window.series_unit(report_type);
+        let has_heatmap = window.has_heatmap(report_type);
+        let hourly_series = async {
+            if has_heatmap && unit != TimeUnit::Hour {
+                query.series(TimeUnit::Hour).await.map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+
+        // Sparkline pageviews and visitors come from the series; Umami has no
+        // series of bounces, so those are fetched for a few slices at a time
+        let tz = period::parse_timezone(subject.timezone)?;
+        let slices = period::split_range(
+            &time_range,
+            chart::buckets(&tz, &time_range, unit)
+                .len()
+                .min(sparkline::MAX_POINTS),
+        );
+        let slice_stats = stream::iter(&slices)
+            .map(|(start, end)| query.stats(*start, *end))
+            .buffered(MAX_CONCURRENT_SLICES)
+            .try_collect::<Vec<_>>();
 
-        let (stats, pages, countries, browsers, devices, referrers) = tokio::try_join!(
-            client.get_stats(token, &website.id, start_at, end_at),
-            client.get_metrics(token, &website.id, "path", start_at, end_at, 10),
-            client.get_metrics(token, &website.id, "country", start_at, end_at, 10),
-            client.get_metrics(token, &website.id, "browser", start_at, end_at, 5),
-            client.get_metrics(token, &website.id, "device", start_at, end_at, 5),
-            client.get_metrics(token, &website.id, "referrer", start_at, end_at, 5),
+        let (member_stats, baseline_stats, series, hourly_series, slice_stats, mut sections) = tokio::try_join!(
+            query.member_stats(time_range.start, time_range.end),
+            baseline_stats,
+            query.series(unit),
+            hourly_series,
+            slice_stats,
+            future::try_join_all(subject.sections.iter().map(|s| query.section(s))),
         )?;
+        let stats: Stats = member_stats.iter().cloned().sum();
+
+        for section in sections
+            .iter_mut()
+            .filter(|section| section.kind.shows_visit_share())
+        {
+            section.visits = Some(stats.visits);
+        }
+
+        let contributions = if subject.sources.len() > 1 {
+            subject
+                .sources
+                .iter()
+                .zip(&member_stats)
+                .map(|(source, member)| Contribution::new(source.name, member, &stats))
+                .collect()
+        } else {
+            Vec::new()
+        };
 
+        // The headline is compared with the same period as the rankings
+        let previous = match &baseline_stats {
+            Some(baseline_stats) => baseline_stats.into(),
+            None => stats.comparison.clone(),
+        };
         let bounce_rate = MetricValue {
-            value: if stats.visits > 0.0 {
-                (stats.bounces / stats.visits * 100.0).min(100.0)
-            } else {
-                0.0
-            },
-            prev: if stats.comparison.visits > 0.0 {
-                (stats.comparison.bounces / stats.comparison.visits * 100.0).min(100.0)
-            } else {
-                0.0
-            },
+            value: helpers::bounce_rate(stats.bounces, stats.visits),
+            prev: helpers::bounce_rate(previous.bounces, previous.visits),
         };
 
+        let changes = HeadlineChanges::between(&(&stats).into(), &previous);
+        let baseline = baseline_range.map(|range| Baseline { label: range.label });
+
         let time_spent = helpers::format_time_spent(stats.totaltime, stats.visits);
+        let chart = TrafficChart::new(&tz, &time_range, unit, &series);
+        let sparklines = Sparklines::new(&chart, &slice_stats);
+        let heatmap = has_heatmap
+            .then(|| Heatmap::new(&tz, &time_range, hourly_series.as_ref().unwrap_or(&series)));
 
         Ok(ReportData {
-            website_name: website.name.clone(),
+            website_id: subject.id.to_string(),
+            website_name: subject.name.to_string(),
             date: time_range.label,
-            report_type: report_type.to_string(),
+            start: time_range.start,
+            end: time_range.end,
+            report_type: report_title,
             stats,
             bounce_rate,
             time_spent,
-            pages,
-            countries,
-            browsers,
-            devices,
-            referrers,
+            changes,
+            baseline,
+            chart,
+            heatmap,
+            sparklines,
+            contributions,
+            history: Vec::new(),
+            sections,
         })
     }
 
-    fn render_report(&self, data: &ReportData) -> Result<String> {
+    pub fn render_report(&self, data: &ReportData) -> Result<String> {
         debug!("Rendering report template");
 
         self.template.render("email", &data).map_err(|e| {
@@ -228,63 +622,256 @@ impl ReportGenerator {
         })
     }
 
+    /// Send an email to every recipient, recording each delivery of the
+    /// reports it contains in the ledger. A failed delivery does not stop the
+    /// remaining ones.
     async fn send_email(
         &self,
         config: &SmtpConfig,
         recipients: &[String],
-        subject: &str,
-        html_content: &str,
+        message: &Message<'_>,
+        reports: &[&ReportData],
+        channel: Channel,
     ) -> Result<()> {
         debug!("Sending email to {} recipients", recipients.len());
 
-        let creds = Credentials::new(config.username.clone(), config.password.clone());
+        let mailer = Mailer::new(config)?;
+        let mut first_error = None;
 
-        let tls_parameters = if config.tls {
-            let tls_params = if config.skip_tls_verify {
-                TlsParameters::builder(config.host.clone())
-                    .dangerous_accept_invalid_certs(true)
-                    .build()?
-            } else {
-                TlsParameters::new(config.host.clone())?
+        for recipient in recipients {
+            let started = Instant::now();
+            let result = mailer
+                .send_html(recipient, &message.subject, message.html, &message.images)
+                .await
+                .map(|_| ());
+            match &result {
+                Ok(()) => debug!("Email sent successfully to {}", recipient),
+                Err(e) => error!("{}", e),
+            }
+            let status = match result {
+                Ok(()) => ReportStatus::Sent,
+                Err(_) => ReportStatus::Failed,
             };
-            Tls::Required(tls_params)
-        } else {
-            Tls::None
-        };
+            for report in reports {
+                metrics::count_report(&report.website_name, channel, status);
+            }
 
-        let mailer = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?
-            .credentials(creds)
-            .port(config.port)
-            .tls(tls_parameters)
-            .timeout(Some(std::time::Duration::from_secs(config.timeout_seconds)))
-            .build();
+            if let Some(ledger) = &self.ledger {
+                for report in reports {
+                    let key = DeliveryKey::new(report, recipient, channel);
+                    let delivery = Delivery::new(key, report, &result, started.elapsed());
+                    if let Err(e) = ledger.record(delivery).await {
+                        error!("Failed to record the delivery to {}: {}", recipient, e);
+                    }
+                }
+            }
 
-        let from_address: lettre::message::Mailbox = config.from.parse()?;
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
 
-        for recipient in recipients {
-            let email = Message::builder()
-                .from(from_address.clone())
-                .to(recipient.parse()?)
-                .subject(subject)
-                .multipart(
-                    MultiPart::alternative().singlepart(
-                        lettre::message::SinglePart::builder()
-                            .header(header::ContentType::TEXT_HTML)
-                            .body(html_content.to_string()),
-                    ),
-                )?;
-
-            match mailer.send(email).await {
-                Ok(_) => debug!("Email sent successfully to {}", recipient),
-                Err(e) => {
-                    error!("Failed to send email to {}: {}", recipient, e);
-                    return Err(AppError::Smtp(format!(
-                        "Failed to send email to {recipient}: {e}"
-                    )));
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+/// The Umami queries for a single report, made against every website of the
+/// subject and combined
+struct ReportQuery<'a> {
+    subject: &'a ReportSubject<'a>,
+    range: &'a TimeRange,
+    compare_range: &'a TimeRange,
+}
+
+impl ReportQuery<'_> {
+    /// The stats of each website, in the order of the subject's sources
+    async fn member_stats(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<Stats>> {
+        future::try_join_all(self.subject.sources.iter().map(|source| {
+            source.client.get_stats(
+                source.token,
+                source.website_id,
+                start.timestamp_millis(),
+                end.timestamp_millis(),
+            )
+        }))
+        .await
+    }
+
+    async fn stats(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Stats> {
+        Ok(self.member_stats(start, end).await?.into_iter().sum())
+    }
+
+    async fn series(&self, unit: TimeUnit) -> Result<PageviewSeries> {
+        let series = future::try_join_all(self.subject.sources.iter().map(|source| {
+            source.client.get_pageviews(
+                source.token,
+                source.website_id,
+                self.range.start.timestamp_millis(),
+                self.range.end.timestamp_millis(),
+                unit,
+                self.subject.timezone,
+            )
+        }))
+        .await?;
+
+        // Points of the same bucket are added up when the chart is built
+        Ok(series
+            .into_iter()
+            .fold(PageviewSeries::default(), |mut combined, series| {
+                combined.pageviews.extend(series.pageviews);
+                combined.sessions.extend(series.sessions);
+                combined
+            }))
+    }
+
+    async fn section(&self, section: &SectionConfig) -> Result<ReportSection> {
+        let limit = section.limit();
+        let (mut items, breakdowns, table) = tokio::try_join!(
+            async {
+                match section.kind.metric_type() {
+                    Some(metric_type) => self.ranked_metrics(metric_type, limit).await,
+                    None => Ok(Vec::new()),
                 }
+            },
+            async {
+                Ok(match section.kind {
+                    SectionKind::Events => self.event_breakdowns(limit).await,
+                    _ => Vec::new(),
+                })
+            },
+            self.ranking_table(section.kind.columns(), limit),
+        )?;
+
+        for item in items.iter_mut().filter(|item| item.metric.x.is_empty()) {
+            item.metric.x = match section.kind {
+                SectionKind::Referrers => "Direct / Unknown",
+                _ => "Unknown",
             }
+            .to_string();
         }
 
-        Ok(())
+        Ok(ReportSection {
+            kind: section.kind,
+            title: section.kind.title().to_string(),
+            items,
+            breakdowns,
+            table,
+            visits: None,
+        })
+    }
+
+    /// Fetch several rankings to show side by side, if the section has any
+    async fn ranking_table(
+        &self,
+        columns: &[(MetricType, &str)],
+        limit: u32,
+    ) -> Result<Option<RankingTable>> {
+        if columns.is_empty() {
+            return Ok(None);
+        }
+
+        let columns = future::try_join_all(columns.iter().map(|(metric_type, title)| async {
+            let items = self.ranked_metrics(*metric_type, limit).await?;
+            Ok::<_, AppError>((title.to_string(), items))
+        }))
+        .await?;
+
+        Ok(Some(RankingTable::new(columns)))
+    }
+
+    /// Fetch the top items of a metric, compared with the comparison period.
+    /// A longer ranking is fetched for the comparison period so that items
+    /// just outside the top entries are not reported as new, and from each
+    /// website of a group so that the combined ranking is complete.
+    async fn ranked_metrics(
+        &self,
+        metric_type: MetricType,
+        limit: u32,
+    ) -> Result<Vec<RankedMetric>> {
+        let current_limit = if self.subject.sources.len() > 1 {
+            limit.max(COMPARISON_LIMIT)
+        } else {
+            limit
+        };
+        let metrics = |range: &TimeRange, limit: u32| {
+            let (start, end) = (range.start.timestamp_millis(), range.end.timestamp_millis());
+            async move {
+                let lists = future::try_join_all(self.subject.sources.iter().map(|source| {
+                    source.client.get_metrics(
+                        source.token,
+                        source.website_id,
+                        metric_type,
+                        start,
+                        end,
+                        limit,
+                    )
+                }))
+                .await?;
+                Ok::<_, AppError>(helpers::merge_metrics(lists))
+            }
+        };
+
+        let (mut current, previous) = tokio::try_join!(
+            metrics(self.range, current_limit),
+            metrics(self.compare_range, limit.max(COMPARISON_LIMIT)),
+        )?;
+        current.truncate(limit as usize);
+        Ok(helpers::compare_metrics(current, &previous))
+    }
+
+    /// Fetch the values of the configured event data properties. A property
+    /// that cannot be fetched, such as a misspelled one, is left out rather
+    /// than failing the report.
+    async fn event_breakdowns(&self, limit: u32) -> Vec<Breakdown> {
+        let breakdowns = self
+            .subject
+            .event_properties
+            .iter()
+            .flat_map(|(event, properties)| properties.iter().map(move |p| (event, p)))
+            .map(|(event, property)| async move {
+                let values = |range: &TimeRange| {
+                    let (start, end) =
+                        (range.start.timestamp_millis(), range.end.timestamp_millis());
+                    async move {
+                        let lists =
+                            future::try_join_all(self.subject.sources.iter().map(|source| {
+                                source.client.get_event_data_values(
+                                    source.token,
+                                    source.website_id,
+                                    event,
+                                    property,
+                                    start,
+                                    end,
+                                )
+                            }))
+                            .await?;
+                        Ok::<_, AppError>(helpers::merge_metrics(lists))
+                    }
+                };
+                let (mut items, previous) =
+                    match tokio::try_join!(values(self.range), values(self.compare_range)) {
+                        Ok(values) => values,
+                        Err(e) => {
+                            error!(
+                                "Failed to fetch {} of {} events for {}: {}",
+                                property, event, self.subject.name, e
+                            );
+                            return None;
+                        }
+                    };
+                items.truncate(limit as usize);
+
+                Some(Breakdown {
+                    event: event.clone(),
+                    property: property.clone(),
+                    items: helpers::compare_metrics(items, &previous),
+                })
+            });
+
+        future::join_all(breakdowns)
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
     }
 }
@@ -1,3 +1,5 @@
+use super::models::{MetricComparison, RankedMetric};
+use crate::api::models::Metric;
 use handlebars::{
     Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError,
     RenderErrorReason,
@@ -62,6 +64,76 @@ pub fn format_time_spent(total_time: f64, visits: f64) -> String {
     }
 }
 
+/// Calculate the bounce rate as a percentage of visits
+pub fn bounce_rate(bounces: f64, visits: f64) -> f64 {
+    if visits > 0.0 {
+        (bounces / visits * 100.0).min(100.0)
+    } else {
+        0.0
+    }
+}
+
+/// Attach the previous value and rank movement of each item in `current` from
+/// the same ranking in the comparison period
+pub fn compare_metrics(current: Vec<Metric>, previous: &[Metric]) -> Vec<RankedMetric> {
+    current
+        .into_iter()
+        .enumerate()
+        .map(|(rank, metric)| {
+            let prev = previous
+                .iter()
+                .enumerate()
+                .find(|(_, prev)| prev.x == metric.x);
+
+            let comparison = match prev {
+                Some((prev_rank, prev)) => MetricComparison {
+                    prev: Some(prev.y),
+                    percent: (prev.y != 0.0).then(|| (metric.y - prev.y) / prev.y * 100.0),
+                    rank_change: Some(prev_rank as i64 - rank as i64),
+                    is_new: false,
+                },
+                None => MetricComparison {
+                    is_new: true,
+                    ..Default::default()
+                },
+            };
+            RankedMetric {
+                metric,
+                comparison: Some(comparison),
+            }
+        })
+        .collect()
+}
+
+/// Combine the rankings of several websites into one, adding up the values of
+/// items with the same name
+pub fn merge_metrics(lists: Vec<Vec<Metric>>) -> Vec<Metric> {
+    let mut merged: Vec<Metric> = Vec::new();
+    for metric in lists.into_iter().flatten() {
+        match merged.iter_mut().find(|m| m.x == metric.x) {
+            Some(existing) => existing.y += metric.y,
+            None => merged.push(metric),
+        }
+    }
+    merged.sort_by(|a, b| b.y.total_cmp(&a.y));
+    merged
+}
+
+/// Shade a heatmap cell from near white for no traffic to the accent color at
+/// the peak
+pub fn heat_color(value: f64, peak: f64) -> String {
+    const EMPTY: [f64; 3] = [248.0, 250.0, 252.0];
+    const FULL: [f64; 3] = [99.0, 102.0, 241.0];
+
+    let ratio = if peak > 0.0 {
+        (value / peak).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let [r, g, b] = [0, 1, 2].map(|i| (EMPTY[i] + (FULL[i] - EMPTY[i]) * ratio).round() as u8);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
 /// Calculate percentage and ensure it's between 0-100
 pub fn percentage(
     h: &Helper,
@@ -114,6 +186,28 @@ pub fn format_float(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_merge_metrics() {
+        let metric = |x: &str, y: f64| Metric {
+            x: x.to_string(),
+            y,
+        };
+        let merged = merge_metrics(vec![
+            vec![metric("/", 10.0), metric("/docs", 4.0)],
+            vec![metric("/pricing", 8.0), metric("/docs", 6.0)],
+        ]);
+
+        let ranking: Vec<_> = merged.iter().map(|m| (m.x.as_str(), m.y)).collect();
+        assert_eq!(ranking, [("/", 10.0), ("/docs", 10.0), ("/pricing", 8.0)]);
+    }
+
+    #[test]
+    fn test_heat_color() {
+        assert_eq!(heat_color(0.0, 10.0), "#f8fafc");
+        assert_eq!(heat_color(10.0, 10.0), "#6366f1");
+        assert_eq!(heat_color(5.0, 0.0), "#f8fafc");
+    }
+
     #[test]
     fn test_format_time_spent() {
         assert_eq!(format_time_spent(0.0, 0.0), "0m 0s");
@@ -122,6 +216,37 @@ mod tests {
         assert_eq!(format_time_spent(3600.0, 1.0), "60m 0s");
     }
 
+    #[test]
+    fn test_compare_metrics() {
+        let metric = |x: &str, y| Metric {
+            x: x.to_string(),
+            y,
+        };
+        let current = vec![
+            metric("/blog", 150.0),
+            metric("/", 120.0),
+            metric("/new", 10.0),
+        ];
+        let previous = vec![
+            metric("/", 200.0),
+            metric("/docs", 120.0),
+            metric("/blog", 100.0),
+        ];
+
+        let current = compare_metrics(current, &previous);
+
+        let blog = current[0].comparison.as_ref().unwrap();
+        assert_eq!(blog.prev, Some(100.0));
+        assert_eq!(blog.percent, Some(50.0));
+        assert_eq!(blog.rank_change, Some(2));
+
+        let home = current[1].comparison.as_ref().unwrap();
+        assert_eq!(home.rank_change, Some(-1));
+        assert_eq!(home.percent, Some(-40.0));
+
+        assert!(current[2].comparison.as_ref().unwrap().is_new);
+    }
+
     #[test]
     fn test_handlebars_helpers() {
         let mut handlebars = Handlebars::new();
@@ -1,3 +1,168 @@
+use handlebars::Handlebars;
+
+use crate::error::Result;
+
+pub mod chart;
 pub mod generator;
 pub mod helpers;
 pub mod models;
+pub mod period;
+pub mod sparkline;
+
+macro_rules! template {
+    ($path:literal) => {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/", $path))
+    };
+}
+
+/// Build the template registry with the report and digest templates, the
+/// partials they share and their helpers
+pub fn template_registry() -> Result<Handlebars<'static>> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_template_string("email", template!("email.html"))?;
+    handlebars.register_template_string("digest", template!("digest.html"))?;
+    handlebars.register_partial("styles", template!("partials/styles.html"))?;
+    handlebars.register_partial("trend", template!("partials/trend.html"))?;
+    handlebars.register_partial("report", template!("partials/report.html"))?;
+    handlebars.register_helper("formatNumber", Box::new(helpers::format_number));
+    handlebars.register_helper("percentage", Box::new(helpers::percentage));
+    handlebars.register_helper("formatFloat", Box::new(helpers::format_float));
+    handlebars.set_strict_mode(false);
+    handlebars::handlebars_helper!(sub: |x:f64, y:f64| x - y);
+    handlebars.register_helper("sub", Box::new(sub));
+    handlebars::handlebars_helper!(abs: |x:f64| x.abs());
+    handlebars.register_helper("abs", Box::new(abs));
+    handlebars::handlebars_helper!(heat_color: |value: f64, peak: f64| helpers::heat_color(value, peak));
+    handlebars.register_helper("heatColor", Box::new(heat_color));
+    Ok(handlebars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_report_renders() {
+        let handlebars = template_registry().unwrap();
+        let html = handlebars
+            .render("email", &models::ReportData::sample())
+            .unwrap();
+        assert!(html.contains("example.com"));
+        assert!(html.contains("1,520"));
+        assert!(html.contains("trend-good"));
+        assert!(html.contains("new entrant"));
+        assert!(html.contains("January 01, 14:00: 110 pageviews"));
+        assert!(html.contains(r#"<td class="heatmap-day">Wed</td>"#));
+        assert!(html.contains("background: #6366f1"));
+        assert!(html.contains("cid:sparkline-sample-bounce_rate"));
+        assert!(html.contains("Previous Periods"));
+        assert!(html.contains("December 31, 2024"));
+        assert_eq!(html.matches(r#"<td class="col-r">"#).count(), 2);
+        assert!(html.contains("United States"));
+    }
+
+    #[test]
+    fn test_event_breakdowns_render() {
+        let handlebars = template_registry().unwrap();
+        let mut data = models::ReportData::sample();
+        data.sections[0].breakdowns.push(models::Breakdown {
+            event: "signup".to_string(),
+            property: "plan".to_string(),
+            items: vec![crate::api::models::Metric {
+                x: "enterprise".to_string(),
+                y: 7.0,
+            }
+            .into()],
+        });
+        let html = handlebars.render("email", &data).unwrap();
+        assert!(html.contains("signup by plan"));
+        assert!(html.contains("enterprise"));
+    }
+
+    #[test]
+    fn test_ranking_table_renders() {
+        let handlebars = template_registry().unwrap();
+        let mut data = models::ReportData::sample();
+        let metric = |x: &str| {
+            models::RankedMetric::from(crate::api::models::Metric {
+                x: x.to_string(),
+                y: 3.0,
+            })
+        };
+        data.sections[0].table = Some(models::RankingTable::new(vec![
+            (
+                "Source".to_string(),
+                vec![metric("newsletter"), metric("twitter")],
+            ),
+            ("Campaign".to_string(), vec![metric("spring-sale")]),
+        ]));
+        let html = handlebars.render("email", &data).unwrap();
+        assert!(html.contains("spring-sale"));
+        assert!(html.contains("twitter"));
+    }
+
+    #[test]
+    fn test_visit_share_renders() {
+        let handlebars = template_registry().unwrap();
+        let mut data = models::ReportData::sample();
+        data.sections[0].visits = Some(1224.0);
+        let html = handlebars.render("email", &data).unwrap();
+        assert!(html.contains("50.0% of visits"));
+    }
+
+    #[test]
+    fn test_digest_renders() {
+        let handlebars = template_registry().unwrap();
+        let first = models::ReportData::sample();
+        let mut second = models::ReportData::sample();
+        second.website_id = "other".to_string();
+        second.website_name = "other.example.com".to_string();
+        let digest = models::Digest::new(vec![&first, &second]);
+
+        let html = handlebars.render("digest", &digest).unwrap();
+        assert!(html.contains("2 websites"));
+        assert!(html.contains(r##"href="#site-other""##));
+        assert!(html.contains(r#"<a name="site-other"></a>"#));
+        assert!(!html.contains("<details"));
+        assert!(html.contains("cid:sparkline-other-visitors"));
+        assert!(html.contains("new entrant"));
+
+        let single = models::Digest::new(vec![&first]);
+        assert_eq!(single.websites, "1 website");
+    }
+
+    #[test]
+    fn test_contributions_render() {
+        let handlebars = template_registry().unwrap();
+        let mut data = models::ReportData::sample();
+        let mut member = data.stats.clone();
+        member.pageviews = 380.0;
+        data.contributions.push(models::Contribution::new(
+            "docs.example.com",
+            &member,
+            &data.stats,
+        ));
+
+        let html = handlebars.render("email", &data).unwrap();
+        assert!(html.contains("docs.example.com"));
+        assert!(html.contains("25.0% of total"));
+    }
+
+    #[test]
+    fn test_baseline_renders() {
+        let handlebars = template_registry().unwrap();
+        let mut data = models::ReportData::sample();
+        data.baseline = Some(models::Baseline {
+            label: "vs last year".to_string(),
+        });
+        let html = handlebars.render("email", &data).unwrap();
+        assert!(html.contains("vs last year"));
+        assert_eq!(html.matches(r#"<td class="stat-delta">"#).count(), 4);
+        // The changes move into the delta column rather than being repeated
+        assert_eq!(html.matches(r#"<span class="trend "#).count(), 4);
+
+        data.baseline = None;
+        let html = handlebars.render("email", &data).unwrap();
+        assert!(!html.contains(r#"<td class="stat-delta">"#));
+    }
+}
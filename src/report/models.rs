@@ -1,17 +1,576 @@
-use crate::api::models::{Metric, MetricValue, Stats};
-use serde::Serialize;
+use super::{
+    chart::{Heatmap, TrafficChart},
+    helpers,
+    period::TimeRange,
+    sparkline::Sparklines,
+};
+use crate::{
+    api::models::{
+        Metric, MetricValue, PageviewSeries, SeriesPoint, Stats, StatsComparison, TimeUnit,
+    },
+    config::models::SectionKind,
+    history::{HistoryEntry, SendStatus},
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Serialize, Serializer};
+
+/// Changes smaller than this are reported as flat
+const FLAT_THRESHOLD: f64 = 0.05;
 
 #[derive(Debug, Serialize)]
 pub struct ReportData {
+    pub website_id: String,
     pub website_name: String,
     pub date: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
     pub report_type: String,
     pub stats: Stats,
     pub bounce_rate: MetricValue,
     pub time_spent: String,
-    pub pages: Vec<Metric>,
-    pub countries: Vec<Metric>,
-    pub browsers: Vec<Metric>,
-    pub devices: Vec<Metric>,
-    pub referrers: Vec<Metric>,
+    pub changes: HeadlineChanges,
+    pub baseline: Option<Baseline>,
+    pub chart: TrafficChart,
+    /// Traffic by weekday and hour, for daily and weekly reports
+    pub heatmap: Option<Heatmap>,
+    /// Headline stats over slices of the report window, embedded as images
+    pub sparklines: Sparklines,
+    /// Each website's share of the totals, for group reports
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contributions: Vec<Contribution>,
+    /// Earlier reports of the same type, most recent first
+    pub history: Vec<HistoryEntry>,
+    /// Rendered in rows of two sections side by side
+    #[serde(serialize_with = "serialize_section_rows")]
+    pub sections: Vec<ReportSection>,
+}
+
+/// The stats of one website of a group, and its share of the group's totals
+#[derive(Debug, Serialize)]
+pub struct Contribution {
+    pub website_name: String,
+    pub pageviews: f64,
+    pub visitors: f64,
+    pub pageviews_percent: f64,
+    pub visitors_percent: f64,
+}
+
+impl Contribution {
+    pub fn new(website_name: &str, stats: &Stats, totals: &Stats) -> Self {
+        let percent = |value: f64, total: f64| {
+            if total > 0.0 {
+                value / total * 100.0
+            } else {
+                0.0
+            }
+        };
+
+        Self {
+            website_name: website_name.to_string(),
+            pageviews: stats.pageviews,
+            visitors: stats.visitors,
+            pageviews_percent: percent(stats.pageviews, totals.pageviews),
+            visitors_percent: percent(stats.visitors, totals.visitors),
+        }
+    }
+}
+
+/// The reports of several websites for the same period, sent to a recipient
+/// in a single email
+#[derive(Debug, Serialize)]
+pub struct Digest<'a> {
+    pub report_type: String,
+    pub date: String,
+    /// The number of websites, such as "3 websites"
+    pub websites: String,
+    pub reports: Vec<&'a ReportData>,
+}
+
+impl<'a> Digest<'a> {
+    /// Group the reports, which should cover the same period, taking the
+    /// period from the first one
+    pub fn new(reports: Vec<&'a ReportData>) -> Self {
+        let (report_type, date) = reports
+            .first()
+            .map(|report| (report.report_type.clone(), report.date.clone()))
+            .unwrap_or_default();
+        let websites = match reports.len() {
+            1 => "1 website".to_string(),
+            count => format!("{count} websites"),
+        };
+        Self {
+            report_type,
+            date,
+            websites,
+            reports,
+        }
+    }
+}
+
+/// A ranked list of metric items, such as the top pages
+#[derive(Debug, Serialize)]
+pub struct ReportSection {
+    pub kind: SectionKind,
+    pub title: String,
+    pub items: Vec<RankedMetric>,
+    /// Event data property values, for the events section
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breakdowns: Vec<Breakdown>,
+    /// Rankings shown side by side, for combined sections such as campaigns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<RankingTable>,
+    /// Total visits in the period, for sections shown as a share of visits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visits: Option<f64>,
+}
+
+/// A row of the report body: two sections side by side, or one at full width
+#[derive(Debug, Serialize)]
+struct SectionRow<'a> {
+    left: &'a ReportSection,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    right: Option<&'a ReportSection>,
+}
+
+/// Pair up consecutive sections. Sections with side by side rankings take a
+/// row of their own, as does a section left without a neighbour.
+fn section_rows(sections: &[ReportSection]) -> Vec<SectionRow<'_>> {
+    let mut rows = Vec::new();
+    let mut pending = None;
+    for section in sections {
+        if section.table.is_some() {
+            if let Some(left) = pending.take() {
+                rows.push(SectionRow { left, right: None });
+            }
+            rows.push(SectionRow {
+                left: section,
+                right: None,
+            });
+        } else if let Some(left) = pending.take() {
+            rows.push(SectionRow {
+                left,
+                right: Some(section),
+            });
+        } else {
+            pending = Some(section);
+        }
+    }
+    if let Some(left) = pending {
+        rows.push(SectionRow { left, right: None });
+    }
+    rows
+}
+
+fn serialize_section_rows<S: Serializer>(
+    sections: &[ReportSection],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    section_rows(sections).serialize(serializer)
+}
+
+/// Several rankings laid out as columns, where each row holds the items of
+/// the same rank
+#[derive(Debug, Serialize)]
+pub struct RankingTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Option<RankedMetric>>>,
+}
+
+impl RankingTable {
+    pub fn new(columns: Vec<(String, Vec<RankedMetric>)>) -> Self {
+        let depth = columns
+            .iter()
+            .map(|(_, items)| items.len())
+            .max()
+            .unwrap_or(0);
+        let rows = (0..depth)
+            .map(|rank| {
+                columns
+                    .iter()
+                    .map(|(_, items)| items.get(rank).cloned())
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            columns: columns.into_iter().map(|(title, _)| title).collect(),
+            rows,
+        }
+    }
+}
+
+/// The values of an event data property for a single event
+#[derive(Debug, Serialize)]
+pub struct Breakdown {
+    pub event: String,
+    pub property: String,
+    pub items: Vec<RankedMetric>,
+}
+
+/// A metric item of a ranking, with how it compares to the same item in the
+/// comparison period
+#[derive(Debug, Clone, Serialize)]
+pub struct RankedMetric {
+    #[serde(flatten)]
+    pub metric: Metric,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comparison: Option<MetricComparison>,
+}
+
+impl From<Metric> for RankedMetric {
+    fn from(metric: Metric) -> Self {
+        Self {
+            metric,
+            comparison: None,
+        }
+    }
+}
+
+/// How a metric item compares to the same item in the comparison period
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct MetricComparison {
+    /// Value in the comparison period, absent for new entrants
+    pub prev: Option<f64>,
+    /// Relative change in percent
+    pub percent: Option<f64>,
+    /// Places moved up (positive) or down (negative) in the ranking
+    pub rank_change: Option<i64>,
+    pub is_new: bool,
+}
+
+/// The comparison period chosen in the website configuration, when it differs
+/// from the previous period. The headline changes are then relative to it.
+#[derive(Debug, Serialize)]
+pub struct Baseline {
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Up,
+    Down,
+    Flat,
+}
+
+/// Whether a change is an improvement for the metric it belongs to
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sentiment {
+    Good,
+    Bad,
+    Neutral,
+}
+
+/// How a metric moved between the comparison period and the report period
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub current: f64,
+    pub previous: f64,
+    pub delta: f64,
+    /// Relative change in percent, absent when there is nothing to compare against
+    pub percent: Option<f64>,
+    pub direction: Direction,
+    pub sentiment: Sentiment,
+}
+
+impl Change {
+    pub fn new(current: f64, previous: f64, higher_is_better: bool) -> Self {
+        let delta = current - previous;
+        let percent = (previous != 0.0).then(|| delta / previous * 100.0);
+
+        let direction = if delta.abs() < FLAT_THRESHOLD {
+            Direction::Flat
+        } else if delta > 0.0 {
+            Direction::Up
+        } else {
+            Direction::Down
+        };
+
+        let sentiment = match direction {
+            Direction::Flat => Sentiment::Neutral,
+            Direction::Up if higher_is_better => Sentiment::Good,
+            Direction::Down if !higher_is_better => Sentiment::Good,
+            _ => Sentiment::Bad,
+        };
+
+        Self {
+            current,
+            previous,
+            delta,
+            percent,
+            direction,
+            sentiment,
+        }
+    }
+}
+
+/// Changes of the headline stats shown at the top of a report
+#[derive(Debug, Clone, Serialize)]
+pub struct HeadlineChanges {
+    pub pageviews: Change,
+    pub visitors: Change,
+    pub visits: Change,
+    pub bounce_rate: Change,
+    /// Average time per visit, in seconds
+    pub avg_time: Change,
+}
+
+impl HeadlineChanges {
+    pub fn between(current: &StatsComparison, previous: &StatsComparison) -> Self {
+        let avg_time = |totals: &StatsComparison| {
+            if totals.visits > 0.0 {
+                totals.totaltime / totals.visits
+            } else {
+                0.0
+            }
+        };
+
+        Self {
+            pageviews: Change::new(current.pageviews, previous.pageviews, true),
+            visitors: Change::new(current.visitors, previous.visitors, true),
+            visits: Change::new(current.visits, previous.visits, true),
+            bounce_rate: Change::new(
+                helpers::bounce_rate(current.bounces, current.visits),
+                helpers::bounce_rate(previous.bounces, previous.visits),
+                false,
+            ),
+            avg_time: Change::new(avg_time(current), avg_time(previous), true),
+        }
+    }
+}
+
+impl ReportData {
+    /// Built-in report with made-up numbers, used to preview the template
+    pub fn sample() -> Self {
+        let section = |kind: SectionKind, items: &[(&str, f64)]| ReportSection {
+            kind,
+            title: kind.title().to_string(),
+            items: items
+                .iter()
+                .enumerate()
+                .map(|(rank, (x, y))| RankedMetric {
+                    metric: Metric {
+                        x: x.to_string(),
+                        y: *y,
+                    },
+                    comparison: Some(match rank {
+                        2 => MetricComparison {
+                            is_new: true,
+                            ..Default::default()
+                        },
+                        _ => MetricComparison {
+                            prev: Some(y * 0.8),
+                            percent: Some(25.0),
+                            rank_change: Some(rank as i64),
+                            is_new: false,
+                        },
+                    }),
+                })
+                .collect(),
+            breakdowns: Vec::new(),
+            table: None,
+            visits: None,
+        };
+
+        let stats = Stats {
+            pageviews: 1520.0,
+            visitors: 634.0,
+            visits: 802.0,
+            bounces: 361.0,
+            totaltime: 98044.0,
+            comparison: StatsComparison {
+                pageviews: 1388.0,
+                visitors: 671.0,
+                visits: 790.0,
+                bounces: 375.0,
+                totaltime: 91210.0,
+            },
+        };
+
+        let range = TimeRange {
+            start: Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2025, 1, 1, 23, 59, 59).unwrap(),
+            label: "January 01, 2025".to_string(),
+        };
+        let hourly = |scale: f64| {
+            (0..24)
+                .map(|hour| SeriesPoint {
+                    x: format!("2025-01-01 {hour:02}:00:00"),
+                    y: (scale * (1.0 - 0.06 * (f64::from(hour) - 14.0).abs())).round(),
+                })
+                .collect()
+        };
+        let series = PageviewSeries {
+            pageviews: hourly(110.0),
+            sessions: hourly(46.0),
+        };
+
+        Self {
+            website_id: "sample".to_string(),
+            website_name: "example.com".to_string(),
+            date: "January 01, 2025".to_string(),
+            start: range.start,
+            end: range.end,
+            report_type: "Daily".to_string(),
+            bounce_rate: MetricValue {
+                value: 45.0,
+                prev: 47.5,
+            },
+            time_spent: "2m 2s".to_string(),
+            changes: HeadlineChanges::between(&(&stats).into(), &stats.comparison),
+            stats,
+            baseline: None,
+            chart: TrafficChart::new(&chrono_tz::UTC, &range, TimeUnit::Hour, &series),
+            heatmap: Some(Heatmap::new(&chrono_tz::UTC, &range, &series)),
+            sparklines: Sparklines {
+                pageviews: vec![
+                    41.0, 22.0, 58.0, 151.0, 214.0, 190.0, 236.0, 208.0, 162.0, 118.0, 73.0, 47.0,
+                ],
+                visitors: vec![
+                    18.0, 11.0, 24.0, 63.0, 88.0, 79.0, 97.0, 86.0, 69.0, 50.0, 31.0, 22.0,
+                ],
+                bounce_rate: vec![
+                    54.0, 61.0, 49.0, 44.0, 41.0, 46.0, 43.0, 42.0, 45.0, 47.0, 50.0, 52.0,
+                ],
+            },
+            contributions: Vec::new(),
+            history: [
+                (1388.0, 671.0, 790.0),
+                (1217.0, 598.0, 702.0),
+                (1460.0, 640.0, 815.0),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(days, (pageviews, visitors, visits))| {
+                let start = range.start - Duration::days(days as i64 + 1);
+                HistoryEntry {
+                    website_id: "sample".to_string(),
+                    website_name: "example.com".to_string(),
+                    report_type: "Daily".to_string(),
+                    period: start.format("%B %d, %Y").to_string(),
+                    start,
+                    end: start + Duration::days(1) - Duration::seconds(1),
+                    generated_at: start + Duration::days(1),
+                    status: SendStatus::Sent,
+                    error: None,
+                    pageviews,
+                    visitors,
+                    visits,
+                    bounce_rate: 46.0,
+                }
+            })
+            .collect(),
+            sections: vec![
+                section(
+                    SectionKind::Pages,
+                    &[
+                        ("/", 612.0),
+                        ("/blog/getting-started", 288.0),
+                        ("/pricing", 201.0),
+                        ("/docs", 164.0),
+                        ("/about", 72.0),
+                    ],
+                ),
+                section(
+                    SectionKind::Referrers,
+                    &[
+                        ("Direct / Unknown", 297.0),
+                        ("google.com", 188.0),
+                        ("news.ycombinator.com", 76.0),
+                    ],
+                ),
+                section(
+                    SectionKind::Browsers,
+                    &[("Chrome", 388.0), ("Firefox", 121.0), ("Safari", 97.0)],
+                ),
+                section(
+                    SectionKind::Devices,
+                    &[("desktop", 402.0), ("mobile", 219.0), ("tablet", 13.0)],
+                ),
+                section(
+                    SectionKind::Countries,
+                    &[
+                        ("United States", 241.0),
+                        ("Germany", 98.0),
+                        ("India", 87.0),
+                        ("United Kingdom", 64.0),
+                    ],
+                ),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change() {
+        let change = Change::new(120.0, 100.0, true);
+        assert_eq!(change.delta, 20.0);
+        assert_eq!(change.percent, Some(20.0));
+        assert_eq!(change.direction, Direction::Up);
+        assert_eq!(change.sentiment, Sentiment::Good);
+
+        let change = Change::new(40.0, 50.0, false);
+        assert_eq!(change.direction, Direction::Down);
+        assert_eq!(change.sentiment, Sentiment::Good);
+
+        let change = Change::new(50.0, 50.0, true);
+        assert_eq!(change.direction, Direction::Flat);
+        assert_eq!(change.sentiment, Sentiment::Neutral);
+
+        let change = Change::new(10.0, 0.0, true);
+        assert_eq!(change.percent, None);
+        assert_eq!(change.sentiment, Sentiment::Good);
+    }
+
+    #[test]
+    fn test_section_rows() {
+        let mut sections = ReportData::sample().sections;
+        let rows = section_rows(&sections);
+        let kinds: Vec<_> = rows
+            .iter()
+            .map(|row| (row.left.kind, row.right.map(|right| right.kind)))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (SectionKind::Pages, Some(SectionKind::Referrers)),
+                (SectionKind::Browsers, Some(SectionKind::Devices)),
+                (SectionKind::Countries, None),
+            ]
+        );
+
+        sections[1].table = Some(RankingTable::new(Vec::new()));
+        let rows = section_rows(&sections);
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].right.is_none());
+        assert_eq!(rows[1].left.kind, SectionKind::Referrers);
+        assert_eq!(
+            rows[2].right.map(|right| right.kind),
+            Some(SectionKind::Devices)
+        );
+    }
+
+    #[test]
+    fn test_ranking_table() {
+        let metric = |x: &str| {
+            RankedMetric::from(Metric {
+                x: x.to_string(),
+                y: 1.0,
+            })
+        };
+        let table = RankingTable::new(vec![
+            ("Source".to_string(), vec![metric("google"), metric("bing")]),
+            ("Medium".to_string(), vec![metric("cpc")]),
+        ]);
+
+        assert_eq!(table.columns, ["Source", "Medium"]);
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(table.rows[0][1].as_ref().unwrap().metric.x, "cpc");
+        assert!(table.rows[1][1].is_none());
+    }
 }
@@ -0,0 +1,562 @@
+use chrono::{DateTime, Datelike, Days, Duration, Months, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use tracing::{debug, error};
+
+use crate::{
+    api::models::TimeUnit,
+    config::models::{Comparison, ReportType},
+    error::{AppError, Result},
+};
+
+#[derive(Debug, Clone)]
+pub struct TimeRange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub label: String,
+}
+
+/// The window of time a report covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportWindow {
+    /// The most recently completed period of the report type
+    Latest,
+    /// The period of the report type that contains the given date
+    Containing(NaiveDate),
+    /// An arbitrary range of dates, both inclusive
+    Custom { from: NaiveDate, to: NaiveDate },
+}
+
+impl ReportWindow {
+    /// Name of the report shown in the email badge and subject
+    pub fn title(&self, report_type: &ReportType) -> String {
+        match self {
+            ReportWindow::Custom { .. } => "Custom".to_string(),
+            _ => report_type.to_string(),
+        }
+    }
+
+    /// The bucket size of the traffic chart. Custom ranges are sized by their
+    /// length rather than the report type, to keep the chart readable.
+    pub fn series_unit(&self, report_type: &ReportType) -> TimeUnit {
+        match self.custom_days() {
+            Some(..=2) => TimeUnit::Hour,
+            Some(..=92) => TimeUnit::Day,
+            Some(_) => TimeUnit::Month,
+            None => report_type.series_unit(),
+        }
+    }
+
+    /// Whether the report shows traffic by weekday and hour. Custom ranges
+    /// show it when they are no longer than a week, like weekly reports.
+    pub fn has_heatmap(&self, report_type: &ReportType) -> bool {
+        match self.custom_days() {
+            Some(days) => days <= 7,
+            None => report_type.has_heatmap(),
+        }
+    }
+
+    /// The number of days of a custom range
+    fn custom_days(&self) -> Option<i64> {
+        match self {
+            ReportWindow::Custom { from, to } => Some((*to - *from).num_days() + 1),
+            _ => None,
+        }
+    }
+}
+
+/// Parse the IANA name of a website timezone
+pub fn parse_timezone(timezone: &str) -> Result<Tz> {
+    timezone.parse().map_err(|e| {
+        error!("Invalid timezone {}: {}", timezone, e);
+        AppError::Config(format!("Invalid timezone: {e}"))
+    })
+}
+
+/// Calculate the UTC time range of a report window, using the website's timezone
+/// to determine where days begin and end. Windows that have not ended yet are
+/// rejected, as their reports would be incomplete.
+pub fn calculate_time_range(
+    timezone: &str,
+    report_type: &ReportType,
+    window: &ReportWindow,
+) -> Result<TimeRange> {
+    let tz = parse_timezone(timezone)?;
+    let today = Utc::now().with_timezone(&tz).date_naive();
+
+    let (first, last) = match window {
+        ReportWindow::Latest => period_bounds(report_type, latest_anchor(report_type, today)),
+        ReportWindow::Containing(date) => period_bounds(report_type, *date),
+        ReportWindow::Custom { from, to } => (*from, *to),
+    };
+
+    let label = match (window, report_type) {
+        (ReportWindow::Custom { .. }, _) if first == last => first.format("%B %d, %Y").to_string(),
+        (ReportWindow::Custom { .. }, _)
+        | (
+            _,
+            ReportType::Weekly
+            | ReportType::IsoWeekly
+            | ReportType::WeekToDate
+            | ReportType::MonthToDate,
+        ) => format!(
+            "{} \u{2013} {}",
+            first.format("%B %d"),
+            last.format("%B %d, %Y")
+        ),
+        (_, ReportType::Daily) => first.format("%B %d, %Y").to_string(),
+        (_, ReportType::Monthly) => first.format("%B %Y").to_string(),
+        (_, ReportType::Quarterly) => format!("Q{} {}", first.month0() / 3 + 1, first.year()),
+        (_, ReportType::Yearly) => first.format("%Y").to_string(),
+    };
+    debug!("Calculating {} report for {}", report_type, label);
+
+    if last >= today {
+        return Err(AppError::Config(format!(
+            "The {label} period has not ended yet in {timezone}"
+        )));
+    }
+
+    let start = start_of_day(&tz, first)?;
+    let end = start_of_day(&tz, last + Days::new(1))? - Duration::seconds(1);
+
+    debug!("Time range: {} to {}", start, end);
+    Ok(TimeRange {
+        start: start.with_timezone(&Utc),
+        end: end.with_timezone(&Utc),
+        label,
+    })
+}
+
+/// Split a time range into consecutive slices of equal length
+pub fn split_range(range: &TimeRange, count: usize) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let count = count.max(1) as i32;
+    let length = range.end + Duration::seconds(1) - range.start;
+    let boundary = |i: i32| range.start + length * i / count;
+
+    (0..count)
+        .map(|i| (boundary(i), boundary(i + 1) - Duration::seconds(1)))
+        .collect()
+}
+
+/// The time range to compare a report against, or `None` for the previous
+/// period, which Umami already returns alongside the stats
+pub fn baseline_range(
+    timezone: &str,
+    range: &TimeRange,
+    comparison: &Comparison,
+) -> Result<Option<TimeRange>> {
+    if *comparison == Comparison::PreviousPeriod {
+        return Ok(None);
+    }
+    comparison_range(timezone, range, comparison).map(Some)
+}
+
+/// The time range of the comparison period for a report
+pub fn comparison_range(
+    timezone: &str,
+    range: &TimeRange,
+    comparison: &Comparison,
+) -> Result<TimeRange> {
+    let tz = parse_timezone(timezone)?;
+    let length = range.end + Duration::seconds(1) - range.start;
+
+    let shift = |time: DateTime<Utc>| {
+        let local = time.with_timezone(&tz);
+        match comparison {
+            Comparison::PreviousPeriod => local.checked_sub_signed(length),
+            Comparison::LastYear => local.checked_sub_months(Months::new(12)),
+            Comparison::LastWeek => local.checked_sub_days(Days::new(7)),
+        }
+        .map(|shifted| shifted.with_timezone(&Utc))
+        .ok_or_else(|| AppError::Config(format!("Cannot shift {time} to the comparison period")))
+    };
+
+    // Shift the exclusive end so that month lengths follow the calendar
+    let start = shift(range.start)?;
+    let end = shift(range.end + Duration::seconds(1))? - Duration::seconds(1);
+    debug!("Comparison range: {} to {}", start, end);
+
+    Ok(TimeRange {
+        start,
+        end,
+        label: comparison.label().to_string(),
+    })
+}
+
+/// Check that a range of dates given on the command line doesn't end before
+/// it starts
+pub fn check_date_span(from: NaiveDate, to: NaiveDate) -> Result<()> {
+    if from > to {
+        return Err(AppError::config(format!(
+            "Start date {from} is after end date {to}"
+        )));
+    }
+    Ok(())
+}
+
+/// The dates of the reports to generate to cover every complete period of the
+/// report type between `from` and `to`, in chronological order
+pub fn backfill_anchors(
+    report_type: &ReportType,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<NaiveDate> {
+    // Rolling weeks have no calendar alignment, so tile them from the start of
+    // the span, and progress reports are generated for every day
+    let mut anchor = match report_type {
+        ReportType::Weekly => from + Days::new(6),
+        ReportType::WeekToDate | ReportType::MonthToDate => from,
+        _ => {
+            let (first, last) = period_bounds(report_type, from);
+            if first < from {
+                following_anchor(report_type, last)
+            } else {
+                last
+            }
+        }
+    };
+
+    let mut anchors = Vec::new();
+    while anchor <= to {
+        anchors.push(anchor);
+        anchor = following_anchor(report_type, anchor);
+    }
+    anchors
+}
+
+/// The windows of the complete periods that ended after `last_end` and before
+/// `latest_start`, oldest first and at most `limit` of them, keeping the most
+/// recent when there are more
+pub fn missed_windows(
+    tz: &Tz,
+    report_type: &ReportType,
+    last_end: DateTime<Utc>,
+    latest_start: DateTime<Utc>,
+    limit: usize,
+) -> Vec<ReportWindow> {
+    let from = last_end.with_timezone(tz).date_naive() + Days::new(1);
+    let to = latest_start.with_timezone(tz).date_naive() - Days::new(1);
+    if from > to {
+        return Vec::new();
+    }
+
+    let anchors = backfill_anchors(report_type, from, to);
+    anchors[anchors.len().saturating_sub(limit)..]
+        .iter()
+        .copied()
+        .map(ReportWindow::Containing)
+        .collect()
+}
+
+/// The date that the latest complete period is anchored to on `today`
+fn latest_anchor(report_type: &ReportType, today: NaiveDate) -> NaiveDate {
+    let (first, _) = period_bounds(report_type, today);
+    match report_type {
+        ReportType::Daily
+        | ReportType::Weekly
+        | ReportType::WeekToDate
+        | ReportType::MonthToDate => today - Days::new(1),
+        _ => first - Days::new(1),
+    }
+}
+
+/// The first and last dates of the period of the report type anchored to `date`
+fn period_bounds(report_type: &ReportType, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let monday = date - Days::new(date.weekday().num_days_from_monday().into());
+    let first_of_month = date.with_day(1).unwrap();
+
+    match report_type {
+        ReportType::Daily => (date, date),
+        ReportType::Weekly => (date - Days::new(6), date),
+        ReportType::IsoWeekly => (monday, monday + Days::new(6)),
+        ReportType::Monthly => (
+            first_of_month,
+            first_of_month + Months::new(1) - Days::new(1),
+        ),
+        ReportType::Quarterly => {
+            let first = NaiveDate::from_ymd_opt(date.year(), date.month0() / 3 * 3 + 1, 1).unwrap();
+            (first, first + Months::new(3) - Days::new(1))
+        }
+        ReportType::Yearly => {
+            let first = NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap();
+            (first, first + Months::new(12) - Days::new(1))
+        }
+        ReportType::WeekToDate => (monday, date),
+        ReportType::MonthToDate => (first_of_month, date),
+    }
+}
+
+/// The anchor of the period immediately after the one ending on `last`
+fn following_anchor(report_type: &ReportType, last: NaiveDate) -> NaiveDate {
+    match report_type {
+        ReportType::Weekly => last + Days::new(7),
+        ReportType::WeekToDate | ReportType::MonthToDate => last + Days::new(1),
+        _ => period_bounds(report_type, last + Days::new(1)).1,
+    }
+}
+
+fn start_of_day(tz: &Tz, date: NaiveDate) -> Result<DateTime<Tz>> {
+    tz.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .ok_or_else(|| AppError::Config(format!("Invalid local time for {date} in {tz}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_period_bounds() {
+        let day = date("2025-03-15");
+        assert_eq!(period_bounds(&ReportType::Daily, day), (day, day));
+        assert_eq!(
+            period_bounds(&ReportType::Weekly, day),
+            (date("2025-03-09"), day)
+        );
+        assert_eq!(
+            period_bounds(&ReportType::Monthly, day),
+            (date("2025-03-01"), date("2025-03-31"))
+        );
+    }
+
+    #[test]
+    fn test_calendar_period_bounds() {
+        // 2025-03-15 is a Saturday
+        let day = date("2025-03-15");
+        assert_eq!(
+            period_bounds(&ReportType::IsoWeekly, day),
+            (date("2025-03-10"), date("2025-03-16"))
+        );
+        assert_eq!(
+            period_bounds(&ReportType::Quarterly, day),
+            (date("2025-01-01"), date("2025-03-31"))
+        );
+        assert_eq!(
+            period_bounds(&ReportType::Yearly, day),
+            (date("2025-01-01"), date("2025-12-31"))
+        );
+        assert_eq!(
+            period_bounds(&ReportType::WeekToDate, day),
+            (date("2025-03-10"), day)
+        );
+        assert_eq!(
+            period_bounds(&ReportType::MonthToDate, day),
+            (date("2025-03-01"), day)
+        );
+    }
+
+    #[test]
+    fn test_latest_anchor() {
+        let today = date("2025-03-01");
+        assert_eq!(latest_anchor(&ReportType::Daily, today), date("2025-02-28"));
+        assert_eq!(
+            period_bounds(
+                &ReportType::Monthly,
+                latest_anchor(&ReportType::Monthly, date("2025-03-20"))
+            ),
+            (date("2025-02-01"), date("2025-02-28"))
+        );
+        assert_eq!(
+            latest_anchor(&ReportType::IsoWeekly, date("2025-03-12")),
+            date("2025-03-09")
+        );
+        assert_eq!(
+            latest_anchor(&ReportType::Quarterly, date("2025-05-20")),
+            date("2025-03-31")
+        );
+        assert_eq!(
+            latest_anchor(&ReportType::MonthToDate, date("2025-05-20")),
+            date("2025-05-19")
+        );
+    }
+
+    #[test]
+    fn test_period_labels() {
+        let window = ReportWindow::Containing(date("2025-05-20"));
+        let label = |report_type| {
+            calculate_time_range("UTC", &report_type, &window)
+                .unwrap()
+                .label
+        };
+        assert_eq!(label(ReportType::Quarterly), "Q2 2025");
+        assert_eq!(label(ReportType::Yearly), "2025");
+        assert_eq!(
+            label(ReportType::MonthToDate),
+            "May 01 \u{2013} May 20, 2025"
+        );
+    }
+
+    #[test]
+    fn test_window_series_unit() {
+        let custom = |from, to| ReportWindow::Custom {
+            from: date(from),
+            to: date(to),
+        };
+        let daily = ReportType::Daily;
+
+        assert_eq!(ReportWindow::Latest.series_unit(&daily), TimeUnit::Hour);
+        assert!(ReportWindow::Latest.has_heatmap(&daily));
+
+        let two_days = custom("2025-01-01", "2025-01-02");
+        assert_eq!(two_days.series_unit(&daily), TimeUnit::Hour);
+        assert!(two_days.has_heatmap(&ReportType::Monthly));
+
+        let quarter = custom("2025-01-01", "2025-03-31");
+        assert_eq!(quarter.series_unit(&daily), TimeUnit::Day);
+        assert!(!quarter.has_heatmap(&daily));
+
+        let year = custom("2025-01-01", "2025-12-31");
+        assert_eq!(year.series_unit(&daily), TimeUnit::Month);
+    }
+
+    #[test]
+    fn test_titles() {
+        // Titles key the history and ledger, so each report type has its own
+        let title = |report_type| ReportWindow::Latest.title(&report_type);
+        assert_eq!(title(ReportType::Weekly), "Weekly");
+        assert_eq!(title(ReportType::IsoWeekly), "ISO Weekly");
+        assert_eq!(
+            ReportWindow::Custom {
+                from: date("2025-05-01"),
+                to: date("2025-05-20")
+            }
+            .title(&ReportType::IsoWeekly),
+            "Custom"
+        );
+    }
+
+    #[test]
+    fn test_custom_time_range() {
+        let window = ReportWindow::Custom {
+            from: date("2025-01-01"),
+            to: date("2025-01-10"),
+        };
+        let range = calculate_time_range("Asia/Kolkata", &ReportType::Daily, &window).unwrap();
+        assert_eq!(range.start.to_rfc3339(), "2024-12-31T18:30:00+00:00");
+        assert_eq!(range.end.to_rfc3339(), "2025-01-10T18:29:59+00:00");
+        assert_eq!(range.label, "January 01 \u{2013} January 10, 2025");
+    }
+
+    #[test]
+    fn test_incomplete_time_range() {
+        let today = Utc::now().date_naive();
+        for window in [
+            ReportWindow::Containing(today),
+            ReportWindow::Containing(today + Days::new(40)),
+            ReportWindow::Custom {
+                from: today - Days::new(7),
+                to: today,
+            },
+        ] {
+            assert!(calculate_time_range("UTC", &ReportType::Daily, &window).is_err());
+        }
+
+        let yesterday = ReportWindow::Containing(today - Days::new(1));
+        assert!(calculate_time_range("UTC", &ReportType::Daily, &yesterday).is_ok());
+        // Yesterday is in the current month on all but the first day of it
+        let this_month = ReportWindow::Containing(today);
+        assert!(calculate_time_range("UTC", &ReportType::Monthly, &this_month).is_err());
+        assert!(calculate_time_range("UTC", &ReportType::Monthly, &ReportWindow::Latest).is_ok());
+    }
+
+    #[test]
+    fn test_baseline_range() {
+        let window = ReportWindow::Containing(date("2025-02-10"));
+        let range = calculate_time_range("UTC", &ReportType::Monthly, &window).unwrap();
+
+        assert!(baseline_range("UTC", &range, &Comparison::PreviousPeriod)
+            .unwrap()
+            .is_none());
+
+        let last_year = baseline_range("UTC", &range, &Comparison::LastYear)
+            .unwrap()
+            .unwrap();
+        assert_eq!(last_year.start.to_rfc3339(), "2024-02-01T00:00:00+00:00");
+        assert_eq!(last_year.end.to_rfc3339(), "2024-02-29T23:59:59+00:00");
+
+        let last_week = baseline_range("UTC", &range, &Comparison::LastWeek)
+            .unwrap()
+            .unwrap();
+        assert_eq!(last_week.start.to_rfc3339(), "2025-01-25T00:00:00+00:00");
+
+        let previous = comparison_range("UTC", &range, &Comparison::PreviousPeriod).unwrap();
+        assert_eq!(previous.start.to_rfc3339(), "2025-01-04T00:00:00+00:00");
+        assert_eq!(previous.end.to_rfc3339(), "2025-01-31T23:59:59+00:00");
+    }
+
+    #[test]
+    fn test_split_range() {
+        let range = calculate_time_range(
+            "UTC",
+            &ReportType::Daily,
+            &ReportWindow::Containing(date("2025-01-01")),
+        )
+        .unwrap();
+        let slices = split_range(&range, 12);
+        assert_eq!(slices.len(), 12);
+        assert_eq!(slices[0].0, range.start);
+        assert_eq!(slices[0].1.to_rfc3339(), "2025-01-01T01:59:59+00:00");
+        assert_eq!(slices[11].1, range.end);
+    }
+
+    #[test]
+    fn test_backfill_anchors() {
+        assert_eq!(
+            backfill_anchors(&ReportType::Daily, date("2025-01-30"), date("2025-02-01")),
+            [date("2025-01-30"), date("2025-01-31"), date("2025-02-01")]
+        );
+        assert_eq!(
+            backfill_anchors(&ReportType::Weekly, date("2025-01-01"), date("2025-01-20")),
+            [date("2025-01-07"), date("2025-01-14")]
+        );
+        assert_eq!(
+            backfill_anchors(&ReportType::Monthly, date("2025-01-15"), date("2025-04-30")),
+            [date("2025-02-28"), date("2025-03-31"), date("2025-04-30")]
+        );
+    }
+
+    #[test]
+    fn test_missed_windows() {
+        let tz: Tz = "Europe/Berlin".parse().unwrap();
+        let range = |report_type, day: &str| {
+            calculate_time_range(
+                "Europe/Berlin",
+                report_type,
+                &ReportWindow::Containing(date(day)),
+            )
+            .unwrap()
+        };
+
+        // Sent up to Friday, down over the weekend, back on Monday
+        let last = range(&ReportType::Daily, "2025-01-10");
+        let latest = range(&ReportType::Daily, "2025-01-13");
+        assert_eq!(
+            missed_windows(&tz, &ReportType::Daily, last.end, latest.start, 7),
+            [
+                ReportWindow::Containing(date("2025-01-11")),
+                ReportWindow::Containing(date("2025-01-12"))
+            ]
+        );
+        assert_eq!(
+            missed_windows(&tz, &ReportType::Daily, last.end, latest.start, 1),
+            [ReportWindow::Containing(date("2025-01-12"))]
+        );
+
+        let last = range(&ReportType::Daily, "2025-01-12");
+        assert!(missed_windows(&tz, &ReportType::Daily, last.end, latest.start, 7).is_empty());
+
+        let last = range(&ReportType::Monthly, "2024-11-01");
+        let latest = range(&ReportType::Monthly, "2025-02-01");
+        assert_eq!(
+            missed_windows(&tz, &ReportType::Monthly, last.end, latest.start, 7),
+            [
+                ReportWindow::Containing(date("2024-12-31")),
+                ReportWindow::Containing(date("2025-01-31"))
+            ]
+        );
+    }
+}
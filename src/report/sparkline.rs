@@ -0,0 +1,257 @@
+use serde::Serialize;
+
+use super::{chart::TrafficChart, helpers};
+use crate::{api::models::Stats, email::InlineImage, error::Result};
+
+/// Most points drawn in a sparkline; longer windows are split into this many slices
+pub const MAX_POINTS: usize = 12;
+
+/// Size of a sparkline as shown in the email, in CSS pixels
+pub const WIDTH: u32 = 80;
+pub const HEIGHT: u32 = 24;
+
+/// Sparklines are drawn at twice their displayed size to stay sharp on high
+/// density screens
+const SCALE: u32 = 2;
+const LINE_WIDTH: f64 = 3.0;
+const COLOR: [u8; 3] = [99, 102, 241];
+const FILL_ALPHA: f64 = 0.12;
+
+/// The headline stats over consecutive slices of the report window
+#[derive(Debug, Clone, Serialize)]
+pub struct Sparklines {
+    pub pageviews: Vec<f64>,
+    pub visitors: Vec<f64>,
+    pub bounce_rate: Vec<f64>,
+}
+
+impl Sparklines {
+    /// Take pageviews and visitors from the traffic chart, whose sessions are
+    /// what Umami counts as visitors, and the bounce rate that Umami has no
+    /// time series for from the stats of each slice
+    pub fn new(chart: &TrafficChart, slices: &[Stats]) -> Self {
+        let pageviews: Vec<f64> = chart.bars.iter().map(|bar| bar.pageviews).collect();
+        let sessions: Vec<f64> = chart.bars.iter().map(|bar| bar.sessions).collect();
+        Self {
+            pageviews: downsample(&pageviews, slices.len()),
+            visitors: downsample(&sessions, slices.len()),
+            bounce_rate: slices
+                .iter()
+                .map(|s| helpers::bounce_rate(s.bounces, s.visits))
+                .collect(),
+        }
+    }
+
+    /// Render every sparkline as a PNG, referenced from the template as
+    /// `cid:sparkline-<website id>-<metric>` so that the images of several
+    /// websites can share an email
+    pub fn images(&self, website_id: &str) -> Result<Vec<InlineImage>> {
+        [
+            ("pageviews", &self.pageviews),
+            ("visitors", &self.visitors),
+            ("bounce_rate", &self.bounce_rate),
+        ]
+        .into_iter()
+        .map(|(metric, values)| {
+            Ok(InlineImage {
+                content_id: format!("sparkline-{website_id}-{metric}"),
+                png: render(values)?,
+            })
+        })
+        .collect()
+    }
+}
+
+/// Average runs of consecutive values down to at most `points` values, so
+/// that uneven runs don't show up as dips
+fn downsample(values: &[f64], points: usize) -> Vec<f64> {
+    let points = points.min(values.len());
+    (0..points)
+        .map(|i| {
+            let run = &values[i * values.len() / points..(i + 1) * values.len() / points];
+            run.iter().sum::<f64>() / run.len() as f64
+        })
+        .collect()
+}
+
+/// Draw a line chart of the values with a light fill below it, on a
+/// transparent background
+pub fn render(values: &[f64]) -> Result<Vec<u8>> {
+    let (width, height) = (WIDTH * SCALE, HEIGHT * SCALE);
+    let points = plot(values, width as f64, height as f64);
+
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+            let distance = points
+                .windows(2)
+                .map(|segment| distance_to_segment((px, py), segment[0], segment[1]))
+                .fold(f64::INFINITY, f64::min);
+            let line = (LINE_WIDTH / 2.0 + 0.5 - distance).clamp(0.0, 1.0);
+            let fill = if py > line_height_at(&points, px) {
+                FILL_ALPHA
+            } else {
+                0.0
+            };
+
+            let alpha = line + fill * (1.0 - line);
+            if alpha > 0.0 {
+                let offset = ((y * width + x) * 4) as usize;
+                pixels[offset..offset + 3].copy_from_slice(&COLOR);
+                pixels[offset + 3] = (alpha * 255.0).round() as u8;
+            }
+        }
+    }
+
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+    }
+    Ok(png)
+}
+
+/// Place the values evenly across the image, leaving room for the line at the
+/// top and bottom. A flat or single valued series is drawn through the middle.
+fn plot(values: &[f64], width: f64, height: f64) -> Vec<(f64, f64)> {
+    let values = match values {
+        [] => &[0.0, 0.0][..],
+        [value] => &[*value, *value][..],
+        values => values,
+    };
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let margin = LINE_WIDTH;
+    let step = (width - 2.0 * margin) / (values.len() - 1) as f64;
+
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let ratio = if max > min {
+                (value - min) / (max - min)
+            } else {
+                0.5
+            };
+            (
+                margin + i as f64 * step,
+                height - margin - ratio * (height - 2.0 * margin),
+            )
+        })
+        .collect()
+}
+
+/// The height of the line at a horizontal position
+fn line_height_at(points: &[(f64, f64)], x: f64) -> f64 {
+    let first = points[0];
+    let last = points[points.len() - 1];
+    if x <= first.0 {
+        return first.1;
+    }
+    if x >= last.0 {
+        return last.1;
+    }
+
+    points
+        .windows(2)
+        .find(|segment| x <= segment[1].0)
+        .map(|segment| {
+            let (a, b) = (segment[0], segment[1]);
+            a.1 + (b.1 - a.1) * (x - a.0) / (b.0 - a.0)
+        })
+        .unwrap_or(last.1)
+}
+
+fn distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((p.0 - a.0 - t * dx).powi(2) + (p.1 - a.1 - t * dy).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plot() {
+        let points = plot(&[1.0, 3.0, 2.0], 100.0, 40.0);
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0], (3.0, 37.0));
+        assert_eq!(points[1], (50.0, 3.0));
+        assert_eq!(points[2].0, 97.0);
+
+        let flat = plot(&[5.0], 100.0, 40.0);
+        assert_eq!(flat.len(), 2);
+        assert_eq!(flat[0].1, 20.0);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let hourly: Vec<f64> = (0..24).map(f64::from).collect();
+        let points = downsample(&hourly, 12);
+        assert_eq!(points.len(), 12);
+        assert_eq!(points[0], 0.5);
+        assert_eq!(points[11], 22.5);
+
+        let daily = [3.0; 31];
+        assert_eq!(downsample(&daily, 12), [3.0; 12]);
+        assert_eq!(downsample(&daily[..7], 12).len(), 7);
+    }
+
+    #[test]
+    fn test_new() {
+        use crate::api::models::TimeUnit;
+        use crate::report::chart::ChartBar;
+
+        let bar = |pageviews: f64, sessions: f64| ChartBar {
+            label: String::new(),
+            title: String::new(),
+            pageviews,
+            sessions,
+            sessions_height: 0,
+            pageviews_height: 0,
+        };
+        let chart = TrafficChart {
+            unit: TimeUnit::Hour,
+            peak: 8.0,
+            bars: vec![bar(2.0, 1.0), bar(4.0, 3.0), bar(6.0, 2.0), bar(8.0, 4.0)],
+        };
+        let slices = [
+            Stats {
+                visits: 4.0,
+                bounces: 1.0,
+                ..Default::default()
+            },
+            Stats {
+                visits: 5.0,
+                bounces: 5.0,
+                ..Default::default()
+            },
+        ];
+
+        let sparklines = Sparklines::new(&chart, &slices);
+        assert_eq!(sparklines.pageviews, [3.0, 7.0]);
+        assert_eq!(sparklines.visitors, [2.0, 3.0]);
+        assert_eq!(sparklines.bounce_rate.len(), 2);
+    }
+
+    #[test]
+    fn test_render() {
+        let png = render(&[4.0, 9.0, 2.0, 7.0]).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().width, WIDTH * SCALE);
+        assert_eq!(reader.info().height, HEIGHT * SCALE);
+    }
+}